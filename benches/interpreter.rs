@@ -0,0 +1,51 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use rustyfungus::{Program, RandomStrategy, StringInputReader};
+
+use std::hint::black_box;
+
+// Every workload loops forever, so each run is exactly this many steps
+const STEPS: u64 = 100_000;
+
+// Counts up on the stack without ever halting
+const ARITHMETIC_LOOP: &str = "\
+0>1+v
+ ^  <";
+
+// Increments the character stored at (2, 2) on every pass
+const SELF_MODIFYING: &str = "\
+>22g1+22pv
+^        <
+  0";
+
+// Pushes and prints a string over and over
+const STRING_HEAVY: &str = "\
+>\"!dlroW olleH\">:#,_v
+^                   <";
+
+// Wanders the grid at random, seeded so every run takes the same path
+const RANDOM_WALK: &str = "\
+v>?<
+>?#?^
+ >?<";
+
+fn run(source: &str) -> String {
+    let mut program = Program::from_source(source, Box::new(StringInputReader::new("")));
+    program.set_random_strategy(RandomStrategy::Seeded(0));
+    program.run_with_limit(STEPS).0
+}
+
+fn interpreter(c: &mut Criterion) {
+    let workloads = [
+        ("arithmetic loop", ARITHMETIC_LOOP),
+        ("self-modifying", SELF_MODIFYING),
+        ("string heavy", STRING_HEAVY),
+        ("random walk", RANDOM_WALK),
+    ];
+
+    for (name, source) in workloads.iter() {
+        c.bench_function(name, |b| b.iter(|| run(black_box(source))));
+    }
+}
+
+criterion_group!(benches, interpreter);
+criterion_main!(benches);
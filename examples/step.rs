@@ -0,0 +1,23 @@
+// Runs a program one step at a time through the library alone, printing where
+// the pointer is and what is on the stack after each instruction
+use rustyfungus::{Program, StringInputReader};
+
+const SOURCE: &str = "\
+v
+>25*\"!iH\">:#,_@";
+
+fn main() {
+    let mut program = Program::from_source(SOURCE, Box::new(StringInputReader::new("")));
+    let (width, height) = program.dimensions();
+    println!("{}x{} grid", width, height);
+
+    while program.is_running() {
+        let (x, y) = (program.xptr(), program.yptr());
+        let cell = program.cell(x, y).unwrap_or(' ');
+
+        program.step();
+
+        println!("({}, {}) '{}' -> {:?} stack={:?} output={:?}",
+                 x, y, cell, program.direction(), program.get_stack(), program.get_last_output());
+    }
+}
@@ -0,0 +1,667 @@
+use rustyfungus::{Program, Token, default_operator_map, describe_token, token_to_char};
+
+use ncurses::*;
+
+use std::collections::HashSet;
+use unicode_width::UnicodeWidthChar;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DELAY_INCREMENT_MS: u64 = 10;
+// How often running until output checks for a key press to interrupt it
+const INTERRUPT_CHECK_STEPS: u64 = 1000;
+
+// Color pairs for cells visited the least through the most, in thirds of the
+// hottest cell's count
+const HEAT_PAIRS: [i16; 3] = [1, 2, 3];
+
+// Operator characters in the legend
+const LEGEND_PAIR: i16 = 4;
+const LEGEND_COLUMN_WIDTH: i32 = 26;
+
+fn init_legend_colors() {
+    start_color();
+    init_pair(LEGEND_PAIR, COLOR_CYAN, COLOR_BLACK);
+}
+
+fn init_heatmap_colors() {
+    start_color();
+    init_pair(HEAT_PAIRS[0], COLOR_BLACK, COLOR_BLUE);
+    init_pair(HEAT_PAIRS[1], COLOR_BLACK, COLOR_YELLOW);
+    init_pair(HEAT_PAIRS[2], COLOR_BLACK, COLOR_RED);
+}
+
+fn heat_pair(count: u64, max_count: u64) -> Option<i16> {
+    if count == 0 || max_count == 0 {
+        return None;
+    }
+
+    let index = ((count * 3 - 1) / max_count).min(2) as usize;
+    Some(HEAT_PAIRS[index])
+}
+
+// Terminals without Unicode get the matching Befunge arrows, with slashes for
+// the diagonals
+fn direction_glyph(delta: (i32, i32), ascii: bool) -> &'static str {
+    if ascii {
+        return match (delta.0.signum(), delta.1.signum()) {
+            (0, -1)           => "^",
+            (0, 1)            => "v",
+            (1, 0)            => ">",
+            (-1, 0)           => "<",
+            (1, -1) | (-1, 1) => "/",
+            (1, 1) | (-1, -1) => "\\",
+            _                 => ".",
+        };
+    }
+
+    match (delta.0.signum(), delta.1.signum()) {
+        (0, -1)  => "↑",
+        (0, 1)   => "↓",
+        (1, 0)   => "→",
+        (-1, 0)  => "←",
+        (1, -1)  => "↗",
+        (1, 1)   => "↘",
+        (-1, 1)  => "↙",
+        (-1, -1) => "↖",
+        _        => "·",
+    }
+}
+
+// Printable ASCII values are shown alongside their character, e.g. 65 ('A')
+fn format_stack_element(value: i32, show_ascii: bool) -> String {
+    if show_ascii && (0x20..0x7f).contains(&value) {
+        format!("{} ('{}')", value, value as u8 as char)
+    } else {
+        format!("{}", value)
+    }
+}
+
+// Picks the top-most stack elements that fit in the given number of rows,
+// keeping the last row for an overflow indicator when they don't all fit
+fn visible_stack(stack: &[i32], rows: usize) -> (Vec<i32>, usize) {
+    let shown = if stack.len() > rows { rows.saturating_sub(1) } else { stack.len() };
+    (stack.iter().rev().take(shown).cloned().collect(), stack.len() - shown)
+}
+
+// A value shown in the watch panel: a grid cell by coordinate, or a stack
+// element counted down from the top (0 is the top)
+pub enum Watch {
+    Cell(i32, i32),
+    Stack(usize),
+}
+
+pub fn parse_watch(value: &str) -> Result<Watch, String> {
+    let error = || format!("Expected a watch like cell:3,4 or stack:0 but got '{}'", value);
+    let mut parts = value.splitn(2, ':');
+
+    match (parts.next(), parts.next()) {
+        (Some("cell"), Some(coordinate)) => {
+            let mut numbers = coordinate.split(',').map(|part| part.trim().parse::<i32>());
+            match (numbers.next(), numbers.next(), numbers.next()) {
+                (Some(Ok(x)), Some(Ok(y)), None) => Ok(Watch::Cell(x, y)),
+                _ => Err(error()),
+            }
+        },
+        (Some("stack"), Some(index)) => index.trim().parse().map(Watch::Stack).map_err(|_| error()),
+        _ => Err(error()),
+    }
+}
+
+fn format_watch(watch: &Watch, program: &Program) -> String {
+    match watch {
+        Watch::Cell(x, y) => match program.cell(*x, *y) {
+            Some(c) => format!("({},{}) '{}'", x, y, c),
+            None    => format!("({},{}) <n/a>", x, y),
+        },
+        Watch::Stack(index) => {
+            let stack = program.get_stack();
+            match stack.len().checked_sub(index + 1) {
+                Some(position) => format!("[{}] {}", index, stack[position]),
+                None           => format!("[{}] <n/a>", index),
+            }
+        },
+    }
+}
+
+// Every operator the program's version understands, in character order, with
+// the digits folded into one line and anything unmapped summed up at the end
+fn legend_entries(program: &Program) -> Vec<(String, &'static str)> {
+    let mut operators: Vec<(char, Token)> = default_operator_map().into_iter()
+        .filter(|(_, token)| *token != Token::Noop && program.version().supports(*token))
+        .collect();
+    operators.sort_by_key(|(c, _)| *c);
+
+    let mut entries = vec![("0-9".to_string(), describe_token(&Token::Int(0)))];
+    entries.extend(operators.iter().map(|(c, token)| (c.to_string(), describe_token(token))));
+    entries.push(("else".to_string(), describe_token(&Token::Noop)));
+    entries
+}
+
+// Wide characters such as CJK and most emoji take two terminal columns, so
+// every cell after them has to shift along. Characters with no width of their
+// own (controls, combining marks) are drawn as a placeholder instead.
+fn display_cell(c: char, ascii: bool) -> (String, i32) {
+    match c.width() {
+        Some(width) if width > 0 => (c.to_string(), width as i32),
+        _ if ascii               => (".".to_string(), 1),
+        _                        => ("·".to_string(), 1),
+    }
+}
+
+// The grid's rows, padded out with spaces so the pointer's cell is drawn (and
+// highlighted) even when it sits past the end of a row or below the last one
+fn padded_rows(grid: &str, x: i32, y: i32) -> Vec<Vec<char>> {
+    let mut rows: Vec<Vec<char>> = grid.split('\n').map(|row| row.chars().collect()).collect();
+
+    if x >= 0 && y >= 0 {
+        let (x, y) = (x as usize, y as usize);
+        if rows.len() <= y {
+            rows.resize(y + 1, vec![]);
+        }
+        if rows[y].len() <= x {
+            rows[y].resize(x + 1, ' ');
+        }
+    }
+
+    rows
+}
+
+// Line drawing characters, or plain ASCII for terminals that garble them
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BorderStyle {
+    Line,
+    Ascii,
+}
+
+impl BorderStyle {
+    // Sides then corners, in the order wborder takes them
+    fn characters(self) -> [chtype; 8] {
+        match self {
+            BorderStyle::Line  => [0; 8],
+            BorderStyle::Ascii => ['|', '|', '-', '-', '+', '+', '+', '+'].map(|c| c as chtype),
+        }
+    }
+
+    fn draw(self, window: WINDOW) {
+        let [left, right, top, bottom, top_left, top_right, bottom_left, bottom_right] = self.characters();
+        wborder(window, left, right, top, bottom, top_left, top_right, bottom_left, bottom_right);
+    }
+}
+
+struct DebugMSWindows {
+    output_window: *mut i8,
+    output_border_window: *mut i8,
+    program_window: *mut i8,
+    stack_window: *mut i8,
+    watch_window: *mut i8,
+    legend_window: *mut i8,
+    show_legend: bool,
+    border: BorderStyle,
+    last_output: String,
+    cumulative_output: String,
+    stack_ascii: bool,
+    heatmap: bool,
+    watches: Vec<Watch>,
+}
+
+impl DebugMSWindows {
+    fn new(options: &DebugOptions, heatmap: bool, watches: Vec<Watch>) -> DebugMSWindows {
+        let mut windows = DebugMSWindows {
+            program_window: std::ptr::null_mut(),
+            output_border_window: std::ptr::null_mut(),
+            output_window: std::ptr::null_mut(),
+            stack_window: std::ptr::null_mut(),
+            watch_window: std::ptr::null_mut(),
+            legend_window: std::ptr::null_mut(),
+            show_legend: options.legend,
+            border: options.border,
+            last_output: String::new(),
+            cumulative_output: String::new(),
+            stack_ascii: options.stack_ascii,
+            heatmap,
+            watches,
+        };
+
+        windows._compute_window_geometry();
+        windows
+    }
+
+    fn _compute_window_geometry(&mut self) {
+        let mut max_x = 0;
+        let mut max_y = 0;
+        getmaxyx(stdscr(), &mut max_y, &mut max_x);
+
+        let right_pane_width = 17;
+        let border_bottom = ((max_y as f32) * 0.8) as i32;
+        let border_right  = max_x - right_pane_width;
+
+        self.program_window = newwin(border_bottom, border_right, 0, 0);
+        self.output_border_window = newwin(max_y - border_bottom, max_x, border_bottom, 0);
+        self.output_window = newwin(max_y - border_bottom - 4, max_x - 2, border_bottom + 3, 1);
+
+        // Watches take the bottom of the right pane, leaving the stack the rest
+        let watch_height = if self.watches.is_empty() {
+            0
+        } else {
+            (self.watches.len() as i32 + 3).min(border_bottom / 2)
+        };
+        self.stack_window = newwin(border_bottom - watch_height, right_pane_width, 0, max_x - right_pane_width);
+        if watch_height > 0 {
+            self.watch_window = newwin(watch_height, right_pane_width, border_bottom - watch_height, max_x - right_pane_width);
+        }
+
+        // The legend overlays the right of the program window, two columns wide
+        // when there's room
+        let legend_width = (LEGEND_COLUMN_WIDTH * 2 + 2).min(border_right);
+        self.legend_window = newwin(border_bottom, legend_width, 0, border_right - legend_width);
+    }
+
+    fn toggle_legend(&mut self) {
+        self.show_legend = !self.show_legend;
+    }
+
+    fn render_legend_window(&mut self, program: &Program) {
+        if !self.show_legend {
+            return;
+        }
+
+        let mut max_x = 0;
+        let mut max_y = 0;
+        getmaxyx(self.legend_window, &mut max_y, &mut max_x);
+
+        let rows = (max_y - 3).max(1);
+        let columns = ((max_x - 2) / LEGEND_COLUMN_WIDTH).max(1);
+        let attributes = if has_colors() { COLOR_PAIR(LEGEND_PAIR) | A_BOLD() } else { A_BOLD() };
+
+        wclear(self.legend_window);
+        mvwaddstr(self.legend_window, 1, 1, "Legend (l hides):");
+        for (index, (operator, description)) in legend_entries(program).iter().enumerate().take((rows * columns) as usize) {
+            let y = 2 + index as i32 % rows;
+            let x = 1 + index as i32 / rows * LEGEND_COLUMN_WIDTH;
+
+            wattron(self.legend_window, attributes);
+            mvwaddstr(self.legend_window, y, x, operator);
+            wattroff(self.legend_window, attributes);
+            mvwaddstr(self.legend_window, y, x + 5, description);
+        }
+
+        self.border.draw(self.legend_window);
+        wrefresh(self.legend_window);
+    }
+
+    fn render_watch_window(&mut self, program: &Program) {
+        if self.watch_window.is_null() {
+            return;
+        }
+
+        let mut max_x = 0;
+        let mut max_y = 0;
+        getmaxyx(self.watch_window, &mut max_y, &mut max_x);
+
+        wclear(self.watch_window);
+        mvwaddstr(self.watch_window, 1, 1, "Watches:");
+        for (index, watch) in self.watches.iter().take((max_y - 3).max(0) as usize).enumerate() {
+            mvwaddstr(self.watch_window, 2 + index as i32, 1, &format_watch(watch, program));
+        }
+
+        self.border.draw(self.watch_window);
+        wrefresh(self.watch_window);
+    }
+
+    fn render_stack_window(&mut self, program: &Program) {
+        wclear(self.stack_window);
+        let mode = if program.in_string_mode() { " String" } else { "" };
+        mvwaddstr(self.stack_window, 1, 1, &format!("Dir: {}{}", direction_glyph(program.delta(), self.border == BorderStyle::Ascii), mode));
+        mvwaddstr(self.stack_window, 2, 1, &format!("Steps: {}", program.instruction_count()));
+        mvwaddstr(self.stack_window, 3, 1, "Stack:");
+
+        let mut max_x = 0;
+        let mut max_y = 0;
+        getmaxyx(self.stack_window, &mut max_y, &mut max_x);
+
+        // Elements start below the header and stop above the bottom border
+        let rows = (max_y - 5).max(0) as usize;
+        let (elements, hidden) = visible_stack(program.get_stack(), rows);
+
+        for (index, element) in elements.iter().enumerate() {
+            mvwaddstr(self.stack_window, (4 + index) as i32, 1, &format_stack_element(*element, self.stack_ascii));
+        }
+
+        if hidden > 0 {
+            mvwaddstr(self.stack_window, (4 + elements.len()) as i32, 1, &format!("... (+{} more)", hidden));
+        }
+
+        self.border.draw(self.stack_window);
+        wrefresh(self.stack_window);
+    }
+
+    fn render_program_window(&mut self, program: &Program) {
+        let max_count = if self.heatmap { program.max_visit_count() } else { 0 };
+
+        // Rows are drawn one below their grid index to clear the top border
+        let rows = padded_rows(&format!("{}", program), program.xptr(), program.yptr());
+        for (y, line) in rows.iter().enumerate() {
+            let y = y as i32;
+
+            let mut column = 0;
+
+            for (x, c) in line.iter().copied().enumerate() {
+                let x = x as i32;
+                let (glyph, width) = display_cell(c, self.border == BorderStyle::Ascii);
+
+                let mut attributes = match heat_pair(program.visit_count(x, y), max_count) {
+                    Some(pair) => COLOR_PAIR(pair),
+                    None       => A_NORMAL(),
+                };
+                if x == program.xptr() && y == program.yptr() {
+                    attributes |= A_REVERSE();
+                }
+
+                wattron(self.program_window, attributes);
+                mvwaddstr(self.program_window, y + 1, column + 1, &glyph);
+                wattroff(self.program_window, attributes);
+
+                column += width;
+            }
+        }
+
+        self.border.draw(self.program_window);
+        wrefresh(self.program_window);
+    }
+
+    fn _render_cumulative_output(&mut self) {
+        mvwaddstr(self.output_window, 0, 0, &self.cumulative_output);
+        wrefresh(self.output_window);
+    }
+
+    fn render_output_window(&mut self) {
+        scrollok(self.output_window, true);
+
+        mvwaddstr(self.output_border_window, 1, 1, &format!("Last Output: {}", self.last_output));
+        mvwaddstr(self.output_border_window, 2, 1, "Cumulative Output:");
+
+        self.border.draw(self.output_border_window);
+        wrefresh(self.output_border_window);
+
+        self._render_cumulative_output();
+    }
+
+    fn render_ended_program_window(&mut self) {
+        self.border.draw(self.output_border_window);
+        mvwaddstr(self.output_border_window, 1, 1, &format!("{:<80}", "Program has ended"));
+        wrefresh(self.output_border_window);
+
+        self._render_cumulative_output();
+    }
+
+    fn render_banner(&mut self, message: &str) {
+        mvwaddstr(self.output_border_window, 0, 2, &format!(" {} ", message));
+        wrefresh(self.output_border_window);
+    }
+
+    fn log_output(&mut self, output: String) {
+        self.last_output = output;
+        self.cumulative_output += &self.last_output;
+    }
+
+    fn render(&mut self, program: &Program) {
+        self.render_program_window(program);
+        self.render_output_window();
+        self.render_stack_window(program);
+        self.render_watch_window(program);
+        self.render_legend_window(program);
+    }
+}
+
+// Reaching either a breakpoint cell or a breakpoint operator pauses the run
+pub struct DebugOptions {
+    pub delay_ms: u64,
+    // Instructions per second while running freely, in place of the delay
+    pub ips: Option<u32>,
+    pub breakpoints: HashSet<(i32, i32)>,
+    pub break_operators: HashSet<Token>,
+    pub stack_ascii: bool,
+    pub heatmap: bool,
+    pub watches: Vec<Watch>,
+    pub legend: bool,
+    pub border: BorderStyle,
+}
+
+enum DebugCommand {
+    Step,
+    Continue,
+    RunUntilOutput,
+    ToggleLegend,
+    Quit,
+}
+
+fn wait_for_debug_command() -> DebugCommand {
+    loop {
+        match getch() {
+            c if c == ' ' as i32 || c == '\n' as i32 || c == KEY_ENTER => return DebugCommand::Step,
+            c if c == 'c' as i32 => return DebugCommand::Continue,
+            c if c == 'o' as i32 => return DebugCommand::RunUntilOutput,
+            c if c == 'l' as i32 => return DebugCommand::ToggleLegend,
+            c if c == 'q' as i32 => return DebugCommand::Quit,
+            _ => {},
+        }
+    }
+}
+
+fn breakpoint_message(program: &Program, options: &DebugOptions) -> Option<String> {
+    let position = (program.xptr(), program.yptr());
+    let token = program.current_token();
+
+    if options.breakpoints.contains(&position) {
+        Some(format!("Breakpoint hit at ({}, {})", position.0, position.1))
+    } else if options.break_operators.contains(&token) {
+        Some(format!("Breakpoint hit on '{}' at ({}, {})", token_to_char(&token), position.0, position.1))
+    } else {
+        None
+    }
+}
+
+// Steps without redrawing until something is printed, a breakpoint is reached
+// or the program halts. Any key interrupts a long silent stretch.
+fn run_until_output(program: &mut Program, options: &DebugOptions) {
+    nodelay(stdscr(), true);
+
+    loop {
+        program.step();
+
+        if !program.is_running()
+            || !program.get_last_output().is_empty()
+            || breakpoint_message(program, options).is_some() {
+            break;
+        }
+
+        if program.instruction_count().is_multiple_of(INTERRUPT_CHECK_STEPS) && getch() != ERR {
+            break;
+        }
+    }
+
+    nodelay(stdscr(), false);
+}
+
+fn step_interval(ips: u32) -> Duration {
+    Duration::from_secs(1) / ips.max(1)
+}
+
+// Sleeps until the next step is due. When drawing a step took longer than the
+// interval the next one runs straight away, and the schedule restarts from
+// there rather than rushing to catch up.
+fn wait_for_next_step(next_step: &mut Instant, interval: Duration) {
+    let now = Instant::now();
+
+    if *next_step > now {
+        thread::sleep(*next_step - now);
+        *next_step += interval;
+    } else {
+        *next_step = now + interval;
+    }
+}
+
+// While running freely, + and - adjust the delay, space pauses and q quits
+fn poll_running_command(delay_ms: &mut u64) -> Option<DebugCommand> {
+    match getch() {
+        c if c == '+' as i32 => {
+            *delay_ms += DELAY_INCREMENT_MS;
+            None
+        },
+        c if c == '-' as i32 => {
+            *delay_ms = delay_ms.saturating_sub(DELAY_INCREMENT_MS);
+            None
+        },
+        c if c == ' ' as i32 => Some(DebugCommand::Step),
+        c if c == 'l' as i32 => Some(DebugCommand::ToggleLegend),
+        c if c == 'q' as i32 => Some(DebugCommand::Quit),
+        _ => None,
+    }
+}
+
+// Space/enter steps once, c runs the rest of the program, o runs until the next
+// output, l toggles the legend and q quits
+pub fn debug_program(program: &mut Program, mut options: DebugOptions) {
+    setlocale(LcCategory::all, "");
+    initscr();
+    noecho();
+    keypad(stdscr(), true);
+    curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+
+    let heatmap = options.heatmap && has_colors();
+    if heatmap {
+        init_heatmap_colors();
+    }
+    if has_colors() {
+        init_legend_colors();
+    }
+
+    let watches = std::mem::take(&mut options.watches);
+    let mut windows = DebugMSWindows::new(&options, heatmap, watches);
+    let mut continuing = false;
+    let mut delay_ms = options.delay_ms;
+    let mut next_step = Instant::now();
+
+    while program.is_running() {
+        clear();
+        refresh();
+
+        windows.render(program);
+
+        if let Some(message) = breakpoint_message(program, &options) {
+            continuing = false;
+            nodelay(stdscr(), false);
+            windows.render_banner(&message);
+        }
+
+        let command = if continuing {
+            poll_running_command(&mut delay_ms)
+        } else {
+            Some(wait_for_debug_command())
+        };
+
+        match command {
+            Some(DebugCommand::Step)     => {
+                continuing = false;
+                nodelay(stdscr(), false);
+            },
+            Some(DebugCommand::Continue) => {
+                continuing = true;
+                nodelay(stdscr(), true);
+            },
+            Some(DebugCommand::ToggleLegend) => {
+                windows.toggle_legend();
+                continue;
+            },
+            Some(DebugCommand::RunUntilOutput) => {
+                // Only the last of those steps can have printed anything
+                run_until_output(program, &options);
+                windows.log_output(program.get_last_output());
+                noecho();
+                continue;
+            },
+            Some(DebugCommand::Quit)     => {
+                endwin();
+                return;
+            },
+            None                         => {},
+        }
+
+        if continuing {
+            if let Some(ips) = options.ips {
+                wait_for_next_step(&mut next_step, step_interval(ips));
+            } else if delay_ms > 0 {
+                thread::sleep(Duration::from_millis(delay_ms));
+            }
+        }
+
+        program.step();
+        windows.log_output(program.get_last_output());
+        noecho();
+    }
+
+    windows.render_ended_program_window();
+    nodelay(stdscr(), false);
+    getch();
+    endwin();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stack_elements_show_printable_characters() {
+        assert_eq!(format_stack_element(65, true), "65 ('A')");
+        assert_eq!(format_stack_element(65, false), "65");
+        assert_eq!(format_stack_element(10, true), "10");
+        assert_eq!(format_stack_element(-1, true), "-1");
+    }
+
+    #[test]
+    fn visible_stack_keeps_a_row_for_the_overflow_count() {
+        let stack: Vec<i32> = (0..100).collect();
+
+        let (shown, hidden) = visible_stack(&stack, 10);
+        assert_eq!(shown, (91..100).rev().collect::<Vec<i32>>());
+        assert_eq!(hidden, 91);
+
+        let (shown, hidden) = visible_stack(&stack[..10], 10);
+        assert_eq!(shown.len(), 10);
+        assert_eq!(hidden, 0);
+    }
+
+    #[test]
+    fn padded_rows_reach_the_pointer() {
+        let rows = padded_rows("ab\nc", 3, 1);
+        assert_eq!(rows, vec![vec!['a', 'b'], vec!['c', ' ', ' ', ' ']]);
+
+        let rows = padded_rows("ab", 0, 2);
+        assert_eq!(rows, vec![vec!['a', 'b'], vec![], vec![' ']]);
+
+        assert_eq!(padded_rows("ab", 1, 0), vec![vec!['a', 'b']]);
+    }
+
+    #[test]
+    fn wide_and_zero_width_cells() {
+        assert_eq!(display_cell('a', false), ("a".to_string(), 1));
+        assert_eq!(display_cell('漢', false), ("漢".to_string(), 2));
+        assert_eq!(display_cell('\u{7}', false), ("·".to_string(), 1));
+        assert_eq!(display_cell('\u{301}', true), (".".to_string(), 1));
+    }
+
+    #[test]
+    fn step_interval_spreads_a_second_over_the_steps() {
+        assert_eq!(step_interval(1), Duration::from_secs(1));
+        assert_eq!(step_interval(4), Duration::from_millis(250));
+        assert_eq!(step_interval(0), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn border_characters() {
+        assert_eq!(BorderStyle::Line.characters(), [0; 8]);
+        assert_eq!(BorderStyle::Ascii.characters(),
+                   ['|', '|', '-', '-', '+', '+', '+', '+'].map(|c| c as chtype));
+    }
+}
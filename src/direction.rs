@@ -3,7 +3,7 @@ use rand::{
     Rng,
 };
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Direction {
     Up,
     Down,
@@ -11,6 +11,28 @@ pub enum Direction {
     Left,
 }
 
+impl Direction {
+    // Movement per step in screen coordinates, where down is +y
+    pub fn delta(self) -> (i32, i32) {
+        match self {
+            Direction::Up    => (0, -1),
+            Direction::Down  => (0, 1),
+            Direction::Right => (1, 0),
+            Direction::Left  => (-1, 0),
+        }
+    }
+
+    pub fn from_delta(delta: (i32, i32)) -> Option<Direction> {
+        match delta {
+            (0, -1) => Some(Direction::Up),
+            (0, 1)  => Some(Direction::Down),
+            (1, 0)  => Some(Direction::Right),
+            (-1, 0) => Some(Direction::Left),
+            _       => None,
+        }
+    }
+}
+
 impl Distribution<Direction> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Direction {
         match rng.gen_range(0, 4) {
@@ -0,0 +1,17 @@
+#[macro_use]
+extern crate lazy_static;
+
+mod direction;
+mod token;
+mod program;
+mod version;
+mod svg;
+#[cfg(feature = "debug-tui")]
+mod ncurses_input;
+
+pub use direction::Direction;
+pub use version::FungeVersion;
+pub use token::{Token, OperatorMap, default_operator_map, describe_token, token_to_char, token_to_char_with, char_to_token, char_to_token_with, lines_to_token_matrix, lines_to_token_matrix_with};
+pub use program::{Program, ProgramState, TraceEntry, TraceMismatch, HaltReason, RuntimeError, StepResult, StepObservation, RandomStrategy, ExtensionOperator, RuntimeWarning, InputReader, StdinInputReader, StringInputReader, EOF};
+#[cfg(feature = "debug-tui")]
+pub use ncurses_input::NcursesInputReader;
@@ -1,179 +1,478 @@
-mod direction;
-mod token;
-mod program;
+#[cfg(feature = "debug-tui")]
+mod debugger;
+mod manifest;
+mod repl;
 
-use token::{Token, char_to_token};
-use program::{Program, StdinInputReader, NcursesInputReader};
-
-#[macro_use]
-extern crate lazy_static;
-
-use ncurses::*;
+use rustyfungus::{Program, FungeVersion, Token, HaltReason, char_to_token, token_to_char, InputReader, StdinInputReader, StringInputReader};
+#[cfg(feature = "debug-tui")]
+use rustyfungus::NcursesInputReader;
 
 use std::fs;
 use std::io;
-use clap::{App, Arg};
+use std::io::{IsTerminal, Read, Write};
+use std::path::Path;
+use clap::{App, Arg, ArgMatches};
+use flate2::read::GzDecoder;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
-fn exit_with_message(message: &str) {
+fn exit_with_message(message: &str) -> ! {
     eprintln!("{}", message);
     std::process::exit(1);
 }
 
-fn lines_to_token_matrix(lines: std::str::Lines) -> Vec<Vec<Token>> {
-    lines.map(|line| {
-        line.chars().map(|c| char_to_token(c)).collect()
-    }).collect()
+// Gzipped sources are recognised by their header rather than the .gz
+// extension, so compressed programs can be piped in too
+fn decode_source(bytes: Vec<u8>) -> Result<String, io::Error> {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut contents = String::new();
+        GzDecoder::new(&bytes[..]).read_to_string(&mut contents)?;
+        Ok(contents)
+    } else {
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
 }
 
-fn load_program(filename: String) -> Result<Program, io::Error> {
-    let contents = fs::read_to_string(filename)?;
-    let parsed_contents = lines_to_token_matrix(contents.lines());
-    Ok(Program::new(parsed_contents, Box::new(NcursesInputReader::new())))
+fn is_url(filename: &str) -> bool {
+    filename.starts_with("http://") || filename.starts_with("https://")
 }
 
-fn run_program(program: &mut Program) {
-    while program.is_running() {
-        program.step();
-        print!("{}", program.get_last_output());
+#[cfg(feature = "net")]
+fn fetch_source(url: &str) -> Result<Vec<u8>, io::Error> {
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| io::Error::other(format!("Could not fetch the program: {}", e)))?;
+
+    let mut bytes = vec![];
+    response.into_reader().read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(not(feature = "net"))]
+fn fetch_source(url: &str) -> Result<Vec<u8>, io::Error> {
+    Err(io::Error::other(format!("Cannot fetch {}: rebuild with the net feature enabled to load programs from URLs", url)))
+}
+
+// A filename of - reads the program source from stdin, and an http(s) URL
+// downloads it
+fn load_program(filename: String, input_reader: Box<dyn InputReader>) -> Result<Program, io::Error> {
+    let bytes = if filename == "-" {
+        let mut bytes = vec![];
+        io::stdin().read_to_end(&mut bytes)?;
+        bytes
+    } else if is_url(&filename) {
+        fetch_source(&filename)?
+    } else {
+        fs::read(filename)?
+    };
+
+    Ok(Program::from_source(&decode_source(bytes)?, input_reader))
+}
+
+// Text files are read as UTF-8, and anything else byte by byte
+fn file_input_reader(path: &str) -> Result<Box<dyn InputReader>, io::Error> {
+    Ok(match String::from_utf8(fs::read(path)?) {
+        Ok(contents) => Box::new(StringInputReader::new(contents)),
+        Err(e)       => Box::new(StringInputReader::from_bytes(e.into_bytes())),
+    })
+}
+
+// Only prompt through ncurses when someone is actually at the terminal, and
+// only under the debugger, which is what sets the terminal up for it
+fn make_input_reader(matches: &ArgMatches) -> Box<dyn InputReader> {
+    if let Some(path) = matches.value_of("input-file") {
+        match file_input_reader(path) {
+            Ok(reader) => return reader,
+            Err(e)     => exit_with_message(&format!("Could not read input file {}: {}", path, e)),
+        }
+    }
+
+    // Once the program source has consumed stdin there is no input left to read
+    if matches.value_of("INPUT") == Some("-") {
+        return Box::new(StringInputReader::new(""));
+    }
+
+    let interactive = if matches.is_present("stdin") || !matches.is_present("debug") {
+        false
+    } else if matches.is_present("interactive") {
+        true
+    } else {
+        io::stdin().is_terminal()
+    };
+
+    if interactive {
+        interactive_input_reader()
+    } else {
+        Box::new(StdinInputReader::new())
     }
 }
 
-struct DebugMSWindows {
-    output_window: *mut i8,
-    output_border_window: *mut i8,
-    program_window: *mut i8,
-    stack_window: *mut i8,
-    last_output: String,
-    cumulative_output: String,
+#[cfg(feature = "debug-tui")]
+fn interactive_input_reader() -> Box<dyn InputReader> {
+    Box::new(NcursesInputReader::new())
 }
 
-impl DebugMSWindows {
-    fn new() -> DebugMSWindows {
-        let mut windows = DebugMSWindows {
-            program_window: std::ptr::null_mut(),
-            output_border_window: std::ptr::null_mut(),
-            output_window: std::ptr::null_mut(),
-            stack_window: std::ptr::null_mut(),
-            last_output: String::new(),
-            cumulative_output: String::new(),
-        };
+// Without ncurses there is no popup to prompt with, so read stdin regardless
+#[cfg(not(feature = "debug-tui"))]
+fn interactive_input_reader() -> Box<dyn InputReader> {
+    Box::new(StdinInputReader::new())
+}
 
-        windows._compute_window_geometry();
-        windows
+// One line per step, written before the instruction runs
+fn write_trace_line<E: Write + ?Sized>(program: &Program, diagnostics: &mut E) -> io::Result<()> {
+    let direction = match program.direction() {
+        Some(direction) => format!("{:?}", direction),
+        None            => format!("{:?}", program.delta()),
+    };
+
+    writeln!(diagnostics, "({},{}) {} {} | stack={:?}",
+             program.xptr(), program.yptr(), direction,
+             token_to_char(&program.current_token()), program.get_stack())
+}
+
+// Brackets the cell under the pointer, padding out the grid if the pointer is
+// past the end of its row, as it can be on the Befunge-93 playfield
+fn mark_pointer(grid: &str, x: i32, y: i32) -> String {
+    let mut rows: Vec<Vec<char>> = grid.split('\n').map(|row| row.chars().collect()).collect();
+    let (x, y) = (x.max(0) as usize, y.max(0) as usize);
+
+    if rows.len() <= y {
+        rows.resize(y + 1, vec![]);
+    }
+    if rows[y].len() <= x {
+        rows[y].resize(x + 1, ' ');
     }
 
-    fn _compute_window_geometry(&mut self) {
-        let mut max_x = 0;
-        let mut max_y = 0;
-        getmaxyx(stdscr(), &mut max_y, &mut max_x);
+    rows.iter()
+        .enumerate()
+        .map(|(row_index, row)| if row_index == y {
+            let before: String = row[..x].iter().collect();
+            let after: String = row[x + 1..].iter().collect();
+            format!("{}[{}]{}", before, row[x], after)
+        } else {
+            row.iter().collect()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
 
-        let right_pane_width = 13;
-        let border_bottom = ((max_y as f32) * 0.8) as i32;
-        let border_right  = max_x - right_pane_width;
+// The state after one step of --debug-text, ending in a separator line
+fn write_frame<W: Write + ?Sized>(program: &Program, out: &mut W) -> io::Result<()> {
+    writeln!(out, "Step {}", program.instruction_count())?;
+    writeln!(out, "{}", mark_pointer(&program.render_grid(), program.xptr(), program.yptr()))?;
+    writeln!(out, "Stack: {:?}", program.get_stack())?;
+    writeln!(out, "Output: {:?}", program.get_last_output())?;
+    writeln!(out, "----")
+}
 
-        self.program_window = newwin(border_bottom, border_right, 0, 0);
-        self.output_border_window = newwin(max_y - border_bottom, max_x, border_bottom, 0);
-        self.output_window = newwin(max_y - border_bottom - 4, max_x - 2, border_bottom + 3, 1);
-        self.stack_window = newwin(border_bottom, right_pane_width, 0, max_x - right_pane_width);
+// Frames are numbered from 0, one written after each step
+fn record_frame(program: &Program, dir: &Path) -> io::Result<()> {
+    let path = dir.join(format!("frame_{:04}.txt", program.instruction_count().saturating_sub(1)));
+    write_frame(program, &mut fs::File::create(path)?)
+}
+
+// Why a run ended, reported through the process exit code. 1 is left for the
+// interpreter's own failures, like a missing program file, and 2-6 for the
+// limits below. A Funge-98 `q` passes its code straight through, so `5q` exits
+// with 5 just as an interrupted run does; --json-status tells the two apart.
+// Codes a process can't exit with, outside 0-255, become 1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum RunStatus {
+    // Carries the code from a Funge-98 `q`, or 0 for `@`
+    Halted(i32),
+    StepLimit,
+    StackLimit,
+    RuntimeError,
+    Interrupted,
+    LikelyLoop,
+}
 
+impl RunStatus {
+    fn of(program: &Program) -> RunStatus {
+        match program.halt_reason() {
+            Some(HaltReason::Quit)          => RunStatus::Halted(program.exit_code().unwrap_or(0)),
+            Some(HaltReason::LeftGrid)      => RunStatus::Halted(0),
+            Some(HaltReason::StepLimit)     => RunStatus::StepLimit,
+            Some(HaltReason::StackOverflow) => RunStatus::StackLimit,
+            Some(HaltReason::LikelyInfiniteLoop) => RunStatus::LikelyLoop,
+            Some(HaltReason::Error(_))      => RunStatus::RuntimeError,
+            None                            => RunStatus::Interrupted,
+        }
     }
 
-    fn render_stack_window(&mut self, program: &Program) {
-        wclear(self.stack_window);
-        mvwaddstr(self.stack_window, 1, 1, "Stack:");
+    fn exit_code(self) -> i32 {
+        match self {
+            RunStatus::Halted(code @ 0..=255) => code,
+            RunStatus::Halted(_)    => 1,
+            RunStatus::StepLimit    => 2,
+            RunStatus::StackLimit   => 3,
+            RunStatus::RuntimeError => 4,
+            RunStatus::Interrupted  => 5,
+            RunStatus::LikelyLoop   => 6,
+        }
+    }
 
-        for (index, element) in program.get_stack().iter().rev().enumerate() {
-            mvwaddstr(self.stack_window, (2 + index) as i32, 1, &format!("{}", element));
+    fn name(self) -> &'static str {
+        match self {
+            RunStatus::Halted(_)    => "halted",
+            RunStatus::StepLimit    => "step-limit",
+            RunStatus::StackLimit   => "stack-limit",
+            RunStatus::RuntimeError => "runtime-error",
+            RunStatus::Interrupted  => "interrupted",
+            RunStatus::LikelyLoop   => "likely-infinite-loop",
         }
+    }
+}
 
-        box_(self.stack_window, 0, 0);
-        wrefresh(self.stack_window);
+// Program output goes to `out`, while anything the interpreter itself has to
+// say goes to `diagnostics` so the two can be told apart
+fn run_program_to<W: Write + ?Sized, E: Write + ?Sized>(
+    program: &mut Program,
+    out: &mut W,
+    diagnostics: &mut E,
+    trace: bool,
+    frame_dir: Option<&Path>,
+) -> io::Result<RunStatus> {
+    if let Some(dir) = frame_dir {
+        fs::create_dir_all(dir)?;
     }
 
-    fn render_program_window(&mut self, program: &Program) {
-        for (y, line) in format!("{}", program).split("\n").enumerate() {
-            let y = y as i32;
+    while program.is_running() {
+        if trace {
+            write_trace_line(program, diagnostics)?;
+        }
 
-            if y - 1 == program.yptr() {
-                for (x, c) in line.to_string().chars().enumerate() {
-                    let x = x as i32;
+        // Whatever has been printed so far might be a prompt for this input
+        if let Token::ReadInt | Token::ReadChar = program.current_token() {
+            out.flush()?;
+        }
 
-                    if x == program.xptr() {
-                        wattron(self.program_window, A_REVERSE());
-                    }
+        program.step();
 
-                    mvwaddch(self.program_window, y, x + 1, c as u32);
+        let output = program.get_last_output_bytes();
+        if !output.is_empty() {
+            out.write_all(&output)?;
+        }
 
-                    if x == program.xptr() {
-                        wattroff(self.program_window, A_REVERSE());
-                    }
-                }
-            } else {
-                mvwaddstr(self.program_window, y, 1, line);
-            }
+        if let Some(dir) = frame_dir {
+            record_frame(program, dir)?;
         }
 
-        box_(self.program_window, 0, 0);
-        wrefresh(self.program_window);
+        for warning in program.take_warnings() {
+            writeln!(diagnostics, "warning: {}", warning)?;
+        }
     }
 
-    fn _render_cumulative_output(&mut self) {
-        mvwaddstr(self.output_window, 0, 0, &self.cumulative_output);
-        wrefresh(self.output_window);
+    out.flush()?;
+    write_halt_diagnostics(program, diagnostics)?;
+    Ok(RunStatus::of(program))
+}
+
+// A plain text stand-in for the debugger that needs no terminal: every step
+// prints a frame to `out`, and the program's output only shows up inside them
+fn debug_text_to<W: Write + ?Sized, E: Write + ?Sized>(
+    program: &mut Program,
+    out: &mut W,
+    diagnostics: &mut E,
+) -> io::Result<RunStatus> {
+    while program.is_running() {
+        program.step();
+        write_frame(program, out)?;
+
+        for warning in program.take_warnings() {
+            writeln!(diagnostics, "warning: {}", warning)?;
+        }
     }
 
-    fn render_output_window(&mut self) {
-        scrollok(self.output_window, true);
+    write_halt_diagnostics(program, diagnostics)?;
+    Ok(RunStatus::of(program))
+}
 
-        mvwaddstr(self.output_border_window, 1, 1, &format!("Last Output: {}", self.last_output));
-        mvwaddstr(self.output_border_window, 2, 1, "Cumulative Output:");
+fn write_halt_diagnostics<E: Write + ?Sized>(program: &Program, diagnostics: &mut E) -> io::Result<()> {
+    match program.halt_reason() {
+        Some(HaltReason::StepLimit)     => {
+            writeln!(diagnostics, "Stopped after reaching the limit of {} steps",
+                     program.max_steps().unwrap_or(program.instruction_count()))?;
+            if !program.quit_dispatched() {
+                writeln!(diagnostics, "hint: program halted by step limit; no @ encountered")?;
+            }
+        },
+        Some(HaltReason::StackOverflow) =>
+            writeln!(diagnostics, "Stopped after the stack reached its maximum depth of {} values",
+                     program.max_stack_depth().unwrap_or(program.get_stack().len()))?,
+        Some(HaltReason::LikelyInfiniteLoop) =>
+            writeln!(diagnostics, "Stopped at ({}, {}): likely infinite loop, the program came back to a state it was already in",
+                     program.xptr(), program.yptr())?,
+        Some(HaltReason::Error(error))  => writeln!(diagnostics, "error: {}", error)?,
+        _                               => {},
+    }
 
-        box_(self.output_border_window, 0, 0);
-        wrefresh(self.output_border_window);
+    Ok(())
+}
 
-        self._render_cumulative_output();
+// When program output reaches stdout. Output is always flushed before the
+// program reads input, so prompts show up whichever is picked.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum OutputBuffering {
+    // Every step's output is written straight away
+    None,
+    Line,
+    // Only once the buffer fills, or the program halts
+    Full,
+}
+
+impl OutputBuffering {
+    fn parse(value: &str) -> Option<OutputBuffering> {
+        match value {
+            "none" => Some(OutputBuffering::None),
+            "line" => Some(OutputBuffering::Line),
+            "full" => Some(OutputBuffering::Full),
+            _      => None,
+        }
     }
+}
 
-    fn render_ended_program_window(&mut self) {
-        box_(self.output_border_window, 0, 0);
-        mvwaddstr(self.output_border_window, 1, 1, &format!("{:<80}", "Program has ended"));
-        wrefresh(self.output_border_window);
+// Flushes after every write
+struct Unbuffered<W: Write>(W);
 
-        self._render_cumulative_output();
+impl<W: Write> Write for Unbuffered<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.0.write(buf)?;
+        self.0.flush()?;
+        Ok(written)
     }
 
-    fn log_output(&mut self, output: String) {
-        self.last_output = output;
-        self.cumulative_output += &self.last_output;
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
     }
+}
 
-    fn render(&mut self, program: &Program) {
-        self.render_program_window(program);
-        self.render_output_window();
-        self.render_stack_window(program);
+fn run_program_buffered<W: Write, E: Write + ?Sized>(
+    program: &mut Program,
+    out: W,
+    diagnostics: &mut E,
+    trace: bool,
+    frame_dir: Option<&Path>,
+    buffering: OutputBuffering,
+) -> io::Result<RunStatus> {
+    match buffering {
+        OutputBuffering::None => run_program_to(program, &mut Unbuffered(out), diagnostics, trace, frame_dir),
+        OutputBuffering::Line => run_program_to(program, &mut io::LineWriter::new(out), diagnostics, trace, frame_dir),
+        OutputBuffering::Full => run_program_to(program, &mut io::BufWriter::new(out), diagnostics, trace, frame_dir),
     }
 }
 
-fn debug_program(program: &mut Program) {
-    initscr();
-    curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+fn run_program(
+    program: &mut Program,
+    trace: bool,
+    frame_dir: Option<&Path>,
+    buffering: OutputBuffering,
+) -> io::Result<RunStatus> {
+    let stdout = io::stdout();
+    run_program_buffered(program, stdout.lock(), &mut io::stderr(), trace, frame_dir, buffering)
+}
 
-    let mut windows = DebugMSWindows::new();
+#[cfg(feature = "debug-tui")]
+fn debug_program(program: &mut Program, matches: &ArgMatches) -> RunStatus {
+    let options = debugger::DebugOptions {
+        delay_ms: matches.value_of("delay").and_then(|value| value.parse().ok()).unwrap_or(0),
+        ips: matches.value_of("ips").and_then(|value| value.parse().ok()),
+        breakpoints: matches.values_of("break")
+            .map(|values| values.filter_map(|value| parse_coordinate(value).ok()).collect())
+            .unwrap_or_default(),
+        break_operators: matches.values_of("break-op")
+            .map(|values| values.filter_map(|value| parse_operator(value).ok()).collect())
+            .unwrap_or_default(),
+        stack_ascii: matches.is_present("stack-ascii"),
+        heatmap: matches.is_present("heatmap"),
+        watches: matches.values_of("watch")
+            .map(|values| values.filter_map(|value| debugger::parse_watch(value).ok()).collect())
+            .unwrap_or_default(),
+        legend: matches.is_present("legend"),
+        border: if matches.is_present("ascii") { debugger::BorderStyle::Ascii } else { debugger::BorderStyle::Line },
+    };
 
-    while program.is_running() {
-        clear();
-        refresh();
+    program.set_heatmap(options.heatmap);
 
-        windows.log_output(program.get_last_output());
-        windows.render(program);
+    debugger::debug_program(program, options);
+    RunStatus::of(program)
+}
 
-        program.step();
-        noecho();
+#[cfg(not(feature = "debug-tui"))]
+fn debug_program(_program: &mut Program, _matches: &ArgMatches) -> RunStatus {
+    exit_with_message("Debug mode is unavailable: rebuild with the debug-tui feature enabled");
+}
+
+fn configure_program(program: &mut Program, matches: &ArgMatches) {
+    program.set_version(match matches.value_of("mode") {
+        Some("98") => FungeVersion::Befunge98,
+        _          => FungeVersion::Befunge93,
+    });
+    if let Some((width, height)) = matches.value_of("field").and_then(|value| parse_field(value).ok()) {
+        program.set_field_size(width, height);
+    }
+    program.set_pad_grid(matches.is_present("pad-grid"));
+    program.set_int_space(!matches.is_present("no-int-space"));
+    program.set_byte_output(matches.is_present("byte-output"));
+    program.set_strict_stack(matches.is_present("strict-stack"));
+    if let Some(limit) = matches.value_of("max-steps").and_then(|value| value.parse().ok()) {
+        program.set_max_steps(limit);
+    }
+    program.set_loop_detection(matches.is_present("detect-loops"));
+    if let Some(limit) = matches.value_of("max-stack-depth").and_then(|value| value.parse().ok()) {
+        program.set_max_stack_depth(limit);
     }
+    if let Some(values) = matches.value_of("stack").and_then(|value| parse_stack(value).ok()) {
+        program.set_initial_stack(values);
+    }
+}
 
-    windows.render_ended_program_window();
-    noecho();
-    endwin();
+#[cfg(feature = "debug-tui")]
+fn validate_watch(value: String) -> Result<(), String> {
+    debugger::parse_watch(&value).map(|_| ())
+}
+
+// Watches only exist in the debugger, which reports itself as unavailable
+#[cfg(not(feature = "debug-tui"))]
+fn validate_watch(_value: String) -> Result<(), String> {
+    Ok(())
+}
+
+fn parse_field(value: &str) -> Result<(i32, i32), String> {
+    let error = || format!("Expected a field size like 80x25 but got '{}'", value);
+    let mut parts = value.split('x').map(|part| part.trim().parse::<i32>());
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(width)), Some(Ok(height)), None) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(error()),
+    }
+}
+
+// Listed bottom to top, so the last value ends up on top of the stack
+fn parse_stack(value: &str) -> Result<Vec<i32>, String> {
+    value.split(',')
+        .map(|part| part.trim().parse::<i32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("Expected a list of integers like 1,2,3 but got '{}'", value))
+}
+
+fn parse_coordinate(value: &str) -> Result<(i32, i32), String> {
+    let error = || format!("Expected a coordinate like 3,4 but got '{}'", value);
+    let mut parts = value.split(',').map(|part| part.trim().parse::<i32>());
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(x)), Some(Ok(y)), None) => Ok((x, y)),
+        _ => Err(error()),
+    }
+}
+
+fn parse_operator(value: &str) -> Result<Token, String> {
+    let mut chars = value.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(char_to_token(c)),
+        _ => Err(format!("Expected a single operator character but got '{}'", value)),
+    }
 }
 
 fn main() {
@@ -186,22 +485,370 @@ fn main() {
              .long("debug")
              .help("Runs the program in debug mode")
              .takes_value(false))
+        .arg(Arg::with_name("debug-text")
+             .long("debug-text")
+             .help("Prints the grid, stack and output after every step as plain text")
+             .conflicts_with("debug")
+             .takes_value(false))
+        .arg(Arg::with_name("record-frames")
+             .long("record-frames")
+             .value_name("dir")
+             .help("Writes the grid, stack and output after every step to numbered files in a directory")
+             .conflicts_with_all(&["debug", "debug-text"])
+             .takes_value(true))
+        .arg(Arg::with_name("delay")
+             .long("delay")
+             .value_name("ms")
+             .help("Sets the pause between steps when running freely in debug mode")
+             .default_value("0")
+             .validator(|value| value.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+             .takes_value(true))
+        .arg(Arg::with_name("ips")
+             .long("ips")
+             .value_name("n")
+             .help("Runs freely in debug mode at this many instructions per second instead of using --delay")
+             .validator(|value| match value.parse::<u32>() {
+                 Ok(0)  => Err("The rate must be at least 1 instruction per second".to_string()),
+                 Ok(_)  => Ok(()),
+                 Err(e) => Err(e.to_string()),
+             })
+             .takes_value(true))
+        .arg(Arg::with_name("break")
+             .long("break")
+             .value_name("x,y")
+             .help("Pauses the debugger when the pointer reaches a cell")
+             .multiple(true)
+             .number_of_values(1)
+             .validator(|value| parse_coordinate(&value).map(|_| ()))
+             .takes_value(true))
+        .arg(Arg::with_name("break-op")
+             .long("break-op")
+             .value_name("op")
+             .help("Pauses the debugger before an operator executes")
+             .multiple(true)
+             .number_of_values(1)
+             .validator(|value| parse_operator(&value).map(|_| ()))
+             .takes_value(true))
+        .arg(Arg::with_name("watch")
+             .long("watch")
+             .value_name("cell:x,y|stack:n")
+             .help("Shows a grid cell or stack element (0 is the top) in the debugger's watch panel")
+             .multiple(true)
+             .number_of_values(1)
+             .validator(validate_watch)
+             .takes_value(true))
+        .arg(Arg::with_name("stack-ascii")
+             .long("stack-ascii")
+             .help("Shows printable stack values as characters in debug mode")
+             .takes_value(false))
+        .arg(Arg::with_name("legend")
+             .long("legend")
+             .help("Shows what each operator does in debug mode; l toggles it")
+             .takes_value(false))
+        .arg(Arg::with_name("ascii")
+             .long("ascii")
+             .help("Draws the debugger with plain ASCII borders and arrows")
+             .takes_value(false))
+        .arg(Arg::with_name("heatmap")
+             .long("heatmap")
+             .help("Tints cells in debug mode by how often they have been executed")
+             .takes_value(false))
+        .arg(Arg::with_name("json-status")
+             .long("json-status")
+             .help("Prints a JSON summary of why the program ended to stderr")
+             .takes_value(false))
+        .arg(Arg::with_name("dump-stack")
+             .long("dump-stack")
+             .alias("dump-stack-on-exit")
+             .help("Prints the stack to stderr when the program ends")
+             .takes_value(false))
+        .arg(Arg::with_name("dump-grid")
+             .long("dump-grid")
+             .value_name("path")
+             .help("Writes the final state of the grid to a file when the program ends")
+             .takes_value(true))
+        .arg(Arg::with_name("pad-grid")
+             .long("pad-grid")
+             .help("Pads every row of the program with spaces to the width of the widest")
+             .takes_value(false))
+        .arg(Arg::with_name("strip-trailing-noops")
+             .long("strip-trailing-noops")
+             .help("Leaves trailing spaces and blank rows out of --dump-grid")
+             .requires("dump-grid")
+             .takes_value(false))
+        .arg(Arg::with_name("no-int-space")
+             .long("no-int-space")
+             .help("Prints integers without the trailing space")
+             .takes_value(false))
+        .arg(Arg::with_name("trace")
+             .long("trace")
+             .help("Logs each instruction to stderr before it runs")
+             .takes_value(false))
+        .arg(Arg::with_name("strict-stack")
+             .long("strict-stack")
+             .help("Warns on stderr whenever an instruction pops an empty stack")
+             .takes_value(false))
+        .arg(Arg::with_name("max-steps")
+             .long("max-steps")
+             .value_name("n")
+             .help("Stops the program after this many steps")
+             .validator(|value| value.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+             .takes_value(true))
+        .arg(Arg::with_name("detect-loops")
+             .long("detect-loops")
+             .help("Stops the program when it comes back to a recent state, as it would in an infinite loop")
+             .takes_value(false))
+        .arg(Arg::with_name("max-stack-depth")
+             .long("max-stack-depth")
+             .value_name("n")
+             .help("Stops the program when its stack grows past this many values")
+             .validator(|value| value.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+             .takes_value(true))
+        .arg(Arg::with_name("stack")
+             .long("stack")
+             .value_name("a,b,c")
+             .help("Pushes these values before the program starts, so the last one is on top")
+             .allow_hyphen_values(true)
+             .validator(|value| parse_stack(&value).map(|_| ()))
+             .takes_value(true))
+        .arg(Arg::with_name("output-buffer")
+             .long("output-buffer")
+             .value_name("mode")
+             .help("Sets when program output is flushed: after every step, at each newline or when the buffer fills")
+             .possible_values(&["none", "line", "full"])
+             .default_value("line")
+             .takes_value(true))
+        .arg(Arg::with_name("byte-output")
+             .long("byte-output")
+             .help("Prints values 0-255 from , as raw bytes instead of UTF-8")
+             .takes_value(false))
+        .arg(Arg::with_name("field")
+             .long("field")
+             .value_name("WxH")
+             .help("Sets the size of the Befunge-93 playfield")
+             .validator(|value| parse_field(&value).map(|_| ()))
+             .takes_value(true))
+        .arg(Arg::with_name("mode")
+             .long("mode")
+             .help("Sets the Befunge version to interpret")
+             .possible_values(&["93", "98"])
+             .default_value("93")
+             .takes_value(true))
+        .arg(Arg::with_name("stdin")
+             .long("stdin")
+             .help("Reads program input from stdin")
+             .conflicts_with("interactive")
+             .takes_value(false))
+        .arg(Arg::with_name("input-file")
+             .long("input-file")
+             .value_name("path")
+             .help("Reads program input from a file instead of stdin")
+             .conflicts_with_all(&["stdin", "interactive"])
+             .takes_value(true))
+        .arg(Arg::with_name("interactive")
+             .long("interactive")
+             .help("Prompts for program input with an ncurses popup")
+             .requires("debug")
+             .takes_value(false))
+        .arg(Arg::with_name("manifest")
+             .long("manifest")
+             .value_name("file")
+             .help("Runs every program listed in a manifest and checks their output")
+             .conflicts_with_all(&["INPUT", "debug"])
+             .takes_value(true))
+        .arg(Arg::with_name("repl")
+             .long("repl")
+             .help("Reads lines of Befunge from stdin and runs each one, printing its output and stack")
+             .conflicts_with_all(&["INPUT", "debug", "manifest"])
+             .takes_value(false))
         .arg(Arg::with_name("INPUT")
-             .help("Sets the Befunge program file to use")
-             .required(true)
+             .help("Sets the Befunge program file to use, - to read it from stdin, or an http(s) URL to fetch it from")
+             .required_unless_one(&["manifest", "repl"])
              .index(1))
         .get_matches();
 
+    if let Some(path) = matches.value_of("manifest") {
+        match manifest::run_manifest(path, |program| configure_program(program, &matches)) {
+            Ok(true)  => return,
+            Ok(false) => std::process::exit(1),
+            Err(e)    => exit_with_message(&e.to_string()),
+        }
+    }
+
+    if matches.is_present("repl") {
+        if let Err(e) = repl::run_repl(|program| configure_program(program, &matches)) {
+            exit_with_message(&e.to_string());
+        }
+        return;
+    }
+
     let filename = matches.value_of("INPUT").unwrap().to_string();
 
-    match load_program(filename) {
+    if filename == "-" && (matches.is_present("stdin") || matches.is_present("interactive")) {
+        exit_with_message("Cannot read program input from stdin when the program itself is read from stdin");
+    }
+
+    match load_program(filename, make_input_reader(&matches)) {
         Ok(mut program) => {
-            if matches.is_present("debug") {
-                debug_program(&mut program);
+            configure_program(&mut program, &matches);
+
+            let status = if matches.is_present("debug") {
+                debug_program(&mut program, &matches)
+            } else if matches.is_present("debug-text") {
+                match debug_text_to(&mut program, &mut io::stdout(), &mut io::stderr()) {
+                    Ok(status) => status,
+                    Err(e)     => exit_with_message(&e.to_string()),
+                }
             } else {
-                run_program(&mut program);
+                let buffering = matches.value_of("output-buffer")
+                    .and_then(OutputBuffering::parse)
+                    .unwrap_or(OutputBuffering::Line);
+
+                let frame_dir = matches.value_of("record-frames").map(Path::new);
+
+                match run_program(&mut program, matches.is_present("trace"), frame_dir, buffering) {
+                    Ok(status) => status,
+                    Err(e)     => exit_with_message(&e.to_string()),
+                }
+            };
+
+            if matches.is_present("dump-stack") {
+                eprintln!("Final stack (bottom to top): {:?}", program.get_stack());
             }
+
+            if let Some(path) = matches.value_of("dump-grid") {
+                let grid = if matches.is_present("strip-trailing-noops") {
+                    program.render_grid_trimmed()
+                } else {
+                    program.render_grid()
+                };
+
+                if let Err(e) = fs::write(path, grid) {
+                    exit_with_message(&e.to_string());
+                }
+            }
+
+            if matches.is_present("json-status") {
+                eprintln!("{{\"status\":\"{}\",\"exit_code\":{},\"steps\":{}}}",
+                          status.name(), status.exit_code(), program.instruction_count());
+            }
+
+            std::process::exit(status.exit_code());
         },
         Err(e) => exit_with_message(&e.to_string()),
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_output_and_diagnostics_are_kept_apart() {
+        let mut program = Program::from_source("+.@", Box::new(StringInputReader::new("")));
+        program.set_strict_stack(true);
+        let mut out = vec![];
+        let mut diagnostics = vec![];
+
+        let status = run_program_to(&mut program, &mut out, &mut diagnostics, false, None).unwrap();
+
+        assert_eq!(status, RunStatus::Halted(0));
+        assert_eq!(String::from_utf8(out).unwrap(), "0 ");
+        assert_eq!(String::from_utf8(diagnostics).unwrap(),
+                   "warning: stack underflow at (0, 0) executing '+'\n");
+    }
+
+    #[test]
+    fn gzipped_sources_run_like_their_plain_twins() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        let plain = fs::read("test/hello.bef").unwrap();
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&plain).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let run = |bytes: Vec<u8>| {
+            let source = decode_source(bytes).unwrap();
+            Program::from_source(&source, Box::new(StringInputReader::new(""))).run_to_completion()
+        };
+
+        assert!(gzipped.starts_with(&GZIP_MAGIC));
+        let expected = fs::read_to_string("test/expected/hello.txt").unwrap();
+        assert_eq!(run(plain), expected);
+        assert_eq!(run(gzipped), expected);
+    }
+
+    // Counts the writes that reach it, which is what the buffering modes save
+    struct CountingWriter {
+        writes: usize,
+        bytes: Vec<u8>,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn buffered_modes_write_less_often() {
+        // Prints "a\nb\n" a character at a time
+        let writes = |buffering: OutputBuffering| {
+            let mut program = Program::from_source("55+\"b\"55+\"a\">:#,_@", Box::new(StringInputReader::new("")));
+            let mut out = CountingWriter { writes: 0, bytes: vec![] };
+            run_program_buffered(&mut program, &mut out, &mut io::sink(), false, None, buffering).unwrap();
+
+            assert_eq!(out.bytes, b"a\nb\n");
+            out.writes
+        };
+
+        assert_eq!(writes(OutputBuffering::None), 4);
+        assert_eq!(writes(OutputBuffering::Line), 2);
+        assert_eq!(writes(OutputBuffering::Full), 1);
+    }
+
+    // Answers a single request with the given status line and body
+    #[cfg(feature = "net")]
+    fn serve_once(status: &'static str, body: &'static str) -> String {
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                line.clear();
+            }
+            write!(reader.get_mut(), "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   status, body.len(), body).unwrap();
+        });
+
+        format!("http://{}/hello.bef", address)
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn fetched_sources_are_the_response_body() {
+        let url = serve_once("200 OK", "64+\"!olleH\">:#,_@");
+
+        assert_eq!(fetch_source(&url).unwrap(), b"64+\"!olleH\">:#,_@");
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn failed_fetches_are_errors() {
+        let url = serve_once("404 Not Found", "");
+
+        assert!(fetch_source(&url).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use rustyfungus::{Program, FungeVersion, HaltReason, StringInputReader};
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Each line is a program path, optionally followed by a tab and the path of a
+// file holding its expected output, and then by another tab and the mode (93
+// or 98) to run it in regardless of --mode. Relative paths are resolved against
+// the manifest's own directory, and blank lines are skipped.
+struct ManifestEntry {
+    program: String,
+    expected_output: Option<String>,
+    version: Option<FungeVersion>,
+}
+
+fn parse_manifest(contents: &str, base: &Path) -> Vec<ManifestEntry> {
+    let resolve = |path: &str| base.join(path.trim()).to_string_lossy().into_owned();
+
+    contents.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            ManifestEntry {
+                program: resolve(parts.next().unwrap_or("")),
+                expected_output: parts.next().map(resolve),
+                version: match parts.next().map(str::trim) {
+                    Some("93") => Some(FungeVersion::Befunge93),
+                    Some("98") => Some(FungeVersion::Befunge98),
+                    _          => None,
+                },
+            }
+        })
+        .collect()
+}
+
+// Programs run with no input, and pass if they stop on their own without an
+// error and print exactly what was expected (when anything was)
+fn check_entry<F: Fn(&mut Program)>(entry: &ManifestEntry, configure: &F) -> Result<(), String> {
+    let mut program = crate::load_program(entry.program.clone(), Box::new(StringInputReader::new("")))
+        .map_err(|e| e.to_string())?;
+    configure(&mut program);
+    if let Some(version) = entry.version {
+        program.set_version(version);
+    }
+
+    let output = program.run_to_completion();
+
+    match program.halt_reason() {
+        Some(HaltReason::StepLimit)     => return Err("stopped by the step limit".to_string()),
+        Some(HaltReason::StackOverflow) => return Err("stack reached its maximum depth".to_string()),
+        Some(HaltReason::LikelyInfiniteLoop) => return Err("stuck in a likely infinite loop".to_string()),
+        Some(HaltReason::Error(error))  => return Err(error.to_string()),
+        _                               => {},
+    }
+
+    if let Some(path) = &entry.expected_output {
+        let expected = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        if output != expected {
+            return Err(format!("expected {:?} but got {:?}", expected, output));
+        }
+    }
+
+    Ok(())
+}
+
+// Returns whether every program passed
+pub fn run_manifest<F: Fn(&mut Program)>(path: &str, configure: F) -> io::Result<bool> {
+    let contents = fs::read_to_string(path)?;
+    let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for entry in parse_manifest(&contents, base) {
+        match check_entry(&entry, &configure) {
+            Ok(()) => {
+                passed += 1;
+                println!("PASS {}", entry.program);
+            },
+            Err(reason) => {
+                failed += 1;
+                println!("FAIL {}: {}", entry.program, reason);
+            },
+        }
+    }
+
+    println!("{} passed, {} failed", passed, failed);
+    Ok(failed == 0)
+}
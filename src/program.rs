@@ -1,10 +1,23 @@
-use crate::token::{Token, token_to_char, char_to_token};
+use crate::token::{Token, OperatorMap, default_operator_map, token_to_char_with, char_to_token_with, lines_to_token_matrix_with};
 use crate::direction::Direction;
+use crate::version::{FungeVersion, BEFUNGE93_WIDTH, BEFUNGE93_HEIGHT};
+use crate::svg;
 
+use rand::{Rng, RngCore, SeedableRng};
+use rand::rngs::StdRng;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::fmt;
 use std::char;
 use std::io;
-use ncurses::{wgetch, mvwgetch, getch, wmove, wrefresh, mvwaddstr, box_, newwin, stdscr, getmaxyx};
+#[cfg(feature = "serde")]
+use std::fs::File;
+#[cfg(feature = "serde")]
+use std::path::Path;
+
+// Value pushed by `~` and `&` once the input is exhausted
+pub const EOF: i32 = -1;
 
 pub trait InputReader {
     fn read_char(&mut self) -> i32;
@@ -12,330 +25,1297 @@ pub trait InputReader {
 }
 
 pub struct StdinInputReader {
-    buffered_line: String,
+    buffered_line: Vec<char>,
     buffered_index: usize,
+    reached_eof: bool,
 }
 
-pub struct NcursesInputReader {
-    input_window: *mut i8,
+pub struct StringInputReader {
+    input: Vec<char>,
+    index: usize,
 }
 
 impl StdinInputReader {
     pub fn new() -> StdinInputReader {
         StdinInputReader {
-            buffered_line: String::new(),
+            buffered_line: vec![],
             buffered_index: 0,
+            reached_eof: false,
         }
     }
 
     fn _read_buffered_line_if_empty(&mut self) {
-        if self.buffered_index >= self.buffered_line.len() {
+        if self.buffered_index >= self.buffered_line.len() && !self.reached_eof {
             self.buffered_index = 0;
 
-            let stdin = io::stdin();
-
-            match stdin.read_line(&mut self.buffered_line) {
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(0)  => self.reached_eof = true,
                 Ok(_)  => {},
-                Err(_) => self.buffered_line = String::new(),
+                Err(_) => {
+                    line.clear();
+                    self.reached_eof = true;
+                },
             };
-        }
-    }
-}
 
-impl NcursesInputReader {
-    pub fn new() -> NcursesInputReader {
-        NcursesInputReader {
-            input_window: std::ptr::null_mut(),
+            self.buffered_line = line.chars().collect();
         }
     }
 
-    fn _init_input_popup(&mut self) {
-        let mut max_x = 0;
-        let mut max_y = 0;
-        getmaxyx(stdscr(), &mut max_y, &mut max_x);
-        let center_x = max_x / 2;
-        let center_y = max_y / 2;
-        let input_width = 40;
-        let input_height = 6;
+    fn _is_exhausted(&self) -> bool {
+        self.reached_eof && self.buffered_index >= self.buffered_line.len()
+    }
+}
 
-        self.input_window = newwin(
-            input_height, input_width,
-            center_y - input_height / 2, center_x - input_width / 2);
+impl Default for StdinInputReader {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    fn _render_input_popup(&mut self, input_type: String) {
-        self._init_input_popup();
+impl StringInputReader {
+    pub fn new<S: Into<String>>(input: S) -> StringInputReader {
+        StringInputReader {
+            input: input.into().chars().collect(),
+            index: 0,
+        }
+    }
 
-        box_(self.input_window, 0, 0);
-        mvwaddstr(self.input_window, 2, 8, &format!("Please input a {}", input_type));
-        wrefresh(self.input_window);
+    // Each byte becomes the character with the same value, so binary input
+    // reads back through `~` byte for byte
+    pub fn from_bytes(input: Vec<u8>) -> StringInputReader {
+        StringInputReader {
+            input: input.into_iter().map(char::from).collect(),
+            index: 0,
+        }
     }
 }
 
-impl InputReader for NcursesInputReader {
-    fn read_char(&mut self) -> i32 {
-        self._render_input_popup("character".to_string());
-        mvwgetch(self.input_window, 3, 9)
-    }
+// Skips ahead to the next integer at or after `start`, returning its value and
+// the index just past its last digit
+fn parse_next_int(chars: &[char], start: usize) -> Option<(i32, usize)> {
+    let is_digit_at = |index: usize| chars.get(index).is_some_and(|c| c.is_ascii_digit());
 
-    fn read_int(&mut self) -> i32 {
-        self._render_input_popup("integer".to_string());
+    let mut index = (start..chars.len())
+        .find(|&index| is_digit_at(index) || (chars[index] == '-' && is_digit_at(index + 1)))?;
 
-        let mut number = 0;
-        let mut c = mvwgetch(self.input_window, 3, 9);
+    let negative = chars[index] == '-';
+    if negative {
+        index += 1;
+    }
 
-        let negative_multiplier = if c == ('-' as i32) {
-            c = wgetch(self.input_window);
-            -1
-        } else {
-            1
-        };
+    let mut value: i32 = 0;
+    while let Some(digit) = chars.get(index).and_then(|c| c.to_digit(10)) {
+        value = value.saturating_mul(10).saturating_add(digit as i32);
+        index += 1;
+    }
 
-        loop {
-            if (c > 57) || (c < 48) {
-                break;
-            }
-            
-            number *= 10;
-            number += (c - 48);
+    Some((if negative { -value } else { value }, index))
+}
 
-            c = wgetch(self.input_window);
+impl InputReader for StringInputReader {
+    fn read_char(&mut self) -> i32 {
+        match self.input.get(self.index) {
+            Some(c) => {
+                self.index += 1;
+                *c as i32
+            },
+            None    => EOF,
         }
-
-        negative_multiplier * number
     }
-}
 
-fn read_int_from_string(s: &String, offset: usize) -> String {
-    s.chars()
-        .enumerate()
-        .skip(offset)
-        .take_while(|(index, c)| c.is_digit(10) || (*c == '-' && *index == offset))
-        .map(|(_, c)| c)
-        .collect::<String>()
+    fn read_int(&mut self) -> i32 {
+        match parse_next_int(&self.input, self.index) {
+            Some((value, end)) => {
+                self.index = end;
+                value
+            },
+            None               => {
+                self.index = self.input.len();
+                EOF
+            },
+        }
+    }
 }
 
 impl InputReader for StdinInputReader {
     fn read_char(&mut self) -> i32 {
         self._read_buffered_line_if_empty();
-        let maybe_char = self.buffered_line.chars().nth(self.buffered_index);
+        if self._is_exhausted() {
+            return EOF;
+        }
+
+        let maybe_char = self.buffered_line.get(self.buffered_index);
         self.buffered_index += 1;
 
         match maybe_char {
-            Some(c) => c as i32,
-            None    => 0,
+            Some(c) => *c as i32,
+            None    => EOF,
         }
     }
 
+    // Reads the next integer, moving on to later lines if this one has none
+    // left, and leaves whatever follows it for the next read
     fn read_int(&mut self) -> i32 {
-        self._read_buffered_line_if_empty();
-        let str_int = read_int_from_string(&self.buffered_line, self.buffered_index);
-        self.buffered_index += str_int.len() + 1;
+        loop {
+            self._read_buffered_line_if_empty();
+            if self._is_exhausted() {
+                return EOF;
+            }
 
-        match str_int.parse::<i32>() {
-            Ok(value) => value,
-            Err(_)    => 0,
+            match parse_next_int(&self.buffered_line, self.buffered_index) {
+                Some((value, end)) => {
+                    self.buffered_index = end;
+                    return value;
+                },
+                None               => self.buffered_index = self.buffered_line.len(),
+            }
         }
     }
 }
 
+// Any Unicode scalar value, astral planes included, survives a `p` followed by
+// a `g` or `,`. Negative values, surrogates and anything past U+10FFFF have no
+// char, so `,` prints them as the replacement character (`p` keeps them as a
+// Token::Value instead, so `g` still reads back the number)
 fn i32_to_char(value: i32) -> char {
-    char::from_u32(value as u32).unwrap()
+    char::from_u32(value as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
 }
 
-fn increment_wrap(value: i32, max_value: i32) -> i32 {
-    if value == max_value - 1 {
+// Treats a zero-sized dimension as a single cell at 0. Takes an i64 so a step
+// from the far edge of i32 space wraps rather than overflowing.
+fn wrap(value: i64, max_value: i32) -> i32 {
+    if max_value <= 0 {
         0
     } else {
-        value + 1
+        value.rem_euclid(max_value as i64) as i32
     }
 }
 
-fn decrement_wrap(value: i32, max_value: i32) -> i32 {
-    if value == 0 {
-        max_value - 1
-    } else {
-        value - 1
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HaltReason {
+    Quit,
+    StepLimit,
+    StackOverflow,
+    LeftGrid,
+    LikelyInfiniteLoop,
+    Error(RuntimeError),
+}
+
+// Malformed state the interpreter can't carry on from, such as a pointer left
+// off the grid by restoring a snapshot or switching version mid-run
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RuntimeError {
+    PointerOutOfBounds { x: i32, y: i32 },
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::PointerOutOfBounds { x, y } =>
+                write!(f, "instruction pointer at ({}, {}) is outside the grid", x, y),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StepResult {
+    Running,
+    Halted,
+    Error(RuntimeError),
+}
+
+// An instruction pointer waiting for its turn while another one runs. Each
+// has its own stack stack, as in Funge-98; only the grid is shared.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct InstructionPointer {
+    xptr: i32,
+    yptr: i32,
+    delta: (i32, i32),
+    string_mode: bool,
+    stack: Vec<i32>,
+    stack_stack: Vec<Vec<i32>>,
+    storage_offset: (i32, i32),
+}
+
+// What one call to step_observed() did. The position and token are those of
+// the instruction that ran, before the pointer moved on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StepObservation {
+    pub result: StepResult,
+    pub x: i32,
+    pub y: i32,
+    pub token: Token,
+    pub produced_output: bool,
+    pub changed_direction: bool,
+    pub modified_grid: bool,
+}
+
+// How `?` picks a direction. RoundRobin ignores the RNG entirely and cycles
+// right, down, left, up so tests can walk every branch.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RandomStrategy {
+    True,
+    Seeded(u64),
+    RoundRobin,
+}
+
+// Non-standard instructions a program can opt into by binding them to a
+// character. PushPosition pushes the pointer's y and then its x.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExtensionOperator {
+    PushPosition,
+}
+
+const ROUND_ROBIN_DIRECTIONS: [Direction; 4] = [Direction::Right, Direction::Down, Direction::Left, Direction::Up];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuntimeWarning {
+    StackUnderflow { x: i32, y: i32, operator: char },
+}
+
+impl fmt::Display for RuntimeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeWarning::StackUnderflow { x, y, operator } =>
+                write!(f, "stack underflow at ({}, {}) executing '{}'", x, y, operator),
+        }
+    }
+}
+
+// One executed instruction, recorded before it runs. `direction` is the
+// pointer's (dx, dy) delta at the time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceEntry {
+    pub step: u64,
+    pub x: i32,
+    pub y: i32,
+    pub direction: (i32, i32),
+    pub op: char,
+    pub stack_len: usize,
+}
+
+// First step at which a re-run left the recorded trace
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceMismatch {
+    pub step: usize,
+    pub expected: (i32, i32),
+    pub actual: (i32, i32),
+    pub expected_direction: (i32, i32),
+    pub actual_direction: (i32, i32),
+}
+
+// Everything needed to rewind a program, apart from its input reader and RNG.
+// With the serde feature this doubles as the checkpoint file format.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProgramState {
+    xptr: i32,
+    yptr: i32,
+    delta: (i32, i32),
+    grid: Vec<Vec<Token>>,
+    stack: Vec<i32>,
+    stack_stack: Vec<Vec<i32>>,
+    storage_offset: (i32, i32),
+    is_running: bool,
+    halt_reason: Option<HaltReason>,
+    string_mode: bool,
+    width: i32,
+    origin_x: i32,
+    origin_y: i32,
+    instruction_count: u64,
+    version: FungeVersion,
+    #[cfg_attr(feature = "serde", serde(default))]
+    other_ips: VecDeque<InstructionPointer>,
+}
+
+impl ProgramState {
+    pub fn xptr(&self) -> i32 {
+        self.xptr
+    }
+
+    pub fn yptr(&self) -> i32 {
+        self.yptr
+    }
+
+    pub fn stack(&self) -> &Vec<i32> {
+        &self.stack
     }
 }
 
 pub struct Program {
     xptr: i32,
     yptr: i32,
-    direction: Direction,
+    // Funge-98 lets `x` set any delta, so the pointer moves by a vector
+    // rather than one of the four directions
+    delta: (i32, i32),
     grid: Vec<Vec<Token>>,
+    // The top of the Funge-98 stack stack. The stacks beneath it live in
+    // `stack_stack`, which stays empty unless `{` is used.
     stack: Vec<i32>,
+    stack_stack: Vec<Vec<i32>>,
+    // Added to the coordinates of `g` and `p`, and set by `{` and `}`
+    storage_offset: (i32, i32),
     is_running: bool,
     string_mode: bool,
     width: i32,
+    // Grid position of the (0, 0) cell addressed by `g` and `p`, which moves
+    // right/down as the grid grows to fit negative coordinates
+    origin_x: i32,
+    origin_y: i32,
+    // Everything printed by the current step; more than one value when `k`
+    // repeats `.` or `,`
     last_output: String,
+    // The same output as written out, where `,` may print raw bytes
+    last_output_bytes: Vec<u8>,
+    byte_output: bool,
     input_reader: Box<dyn InputReader>,
+    rng: Box<dyn RngCore>,
+    round_robin: bool,
+    round_robin_index: usize,
+    instruction_count: u64,
+    max_steps: Option<u64>,
+    max_stack_depth: Option<usize>,
+    halt_reason: Option<HaltReason>,
+    version: FungeVersion,
+    strict_stack: bool,
+    warnings: Vec<RuntimeWarning>,
+    underflowed_this_step: bool,
+    // Whether the last instruction changed the delta of the pointer that ran
+    // it, recorded before the next pointer takes its turn
+    turned_this_step: bool,
+    profiling: bool,
+    operator_counts: HashMap<Token, u64>,
+    tracing: bool,
+    trace: Vec<TraceEntry>,
+    int_space: bool,
+    heatmap: bool,
+    visit_counts: HashMap<(i32, i32), u64>,
+    initial_grid: Vec<Vec<Token>>,
+    start_position: (i32, i32),
+    start_delta: (i32, i32),
+    initial_stack: Vec<i32>,
+    quit_dispatched: bool,
+    exit_code: Option<i32>,
+    extension_operators: HashMap<char, ExtensionOperator>,
+    operator_map: OperatorMap,
+    // The fixed Befunge-93 torus, as (width, height)
+    field: (i32, i32),
+    wrapping: bool,
+    loop_detection: bool,
+    recent_states: VecDeque<u64>,
+    recent_state_set: HashSet<u64>,
+    // Bumped by anything a state fingerprint can't see, like input, randomness
+    // and writes to the grid, so a loop that makes progress through them
+    // isn't mistaken for one that's stuck
+    side_effects: u64,
+    grid_writes: u64,
+    pad_grid: bool,
+    // Pointers created by `t`, in the order they get their turns. The running
+    // pointer lives in the fields above and joins the back after each step.
+    other_ips: VecDeque<InstructionPointer>,
+    // Set when `@` ends the running pointer while others carry on
+    ip_ended: bool,
+}
+
+// How many steps back loop detection remembers
+const LOOP_HISTORY: usize = 1024;
+
+// Furthest a Funge-98 `p` or `s` may stretch the grid along either axis
+const MAX_GRID_EXTENT: i64 = 4096;
+
+// The widest row, but never less than one cell
+fn grid_width(grid: &[Vec<Token>]) -> i32 {
+    grid.iter()
+        .map(|line| line.len())
+        .max()
+        .unwrap_or(0)
+        .max(1) as i32
+}
+
+// Fills short rows out with no-ops so every row is `width` cells long
+fn pad_rows(grid: &mut [Vec<Token>], width: i32) {
+    for row in grid.iter_mut() {
+        if row.len() < width as usize {
+            row.resize(width as usize, Token::Noop);
+        }
+    }
 }
 
 impl Program {
     pub fn new(parsed_contents: Vec<Vec<Token>>, input_reader: Box<dyn InputReader>) -> Program {
-        let max_width = parsed_contents.iter()
-            .map(|line| line.len())
-            .max()
-            .unwrap_or(0) as i32;
+        Program::new_with_rng(parsed_contents, input_reader, Box::new(rand::thread_rng()))
+    }
+
+    pub fn new_with_rng(
+        mut parsed_contents: Vec<Vec<Token>>,
+        input_reader: Box<dyn InputReader>,
+        rng: Box<dyn RngCore>,
+    ) -> Program {
+        // An empty program still needs a cell for the pointer to sit on
+        if parsed_contents.is_empty() {
+            parsed_contents.push(vec![]);
+        }
 
         Program {
             xptr: 0,
             yptr: 0,
-            direction: Direction::Right,
+            delta: Direction::Right.delta(),
+            width: grid_width(&parsed_contents),
+            initial_grid: parsed_contents.clone(),
+            start_position: (0, 0),
+            start_delta: Direction::Right.delta(),
+            initial_stack: vec![],
+            quit_dispatched: false,
+            exit_code: None,
+            extension_operators: HashMap::new(),
+            operator_map: default_operator_map(),
+            field: (BEFUNGE93_WIDTH, BEFUNGE93_HEIGHT),
+            wrapping: true,
+            loop_detection: false,
+            recent_states: VecDeque::new(),
+            recent_state_set: HashSet::new(),
+            side_effects: 0,
+            grid_writes: 0,
+            pad_grid: false,
+            other_ips: VecDeque::new(),
+            ip_ended: false,
             grid: parsed_contents,
             stack: vec![],
+            stack_stack: vec![],
+            storage_offset: (0, 0),
             is_running: true,
             string_mode: false,
-            width: max_width,
+            origin_x: 0,
+            origin_y: 0,
             last_output: String::new(),
-            input_reader: input_reader,
+            last_output_bytes: vec![],
+            byte_output: false,
+            input_reader,
+            rng,
+            round_robin: false,
+            round_robin_index: 0,
+            instruction_count: 0,
+            max_steps: None,
+            max_stack_depth: None,
+            halt_reason: None,
+            version: FungeVersion::Befunge93,
+            strict_stack: false,
+            warnings: vec![],
+            underflowed_this_step: false,
+            turned_this_step: false,
+            profiling: false,
+            operator_counts: HashMap::new(),
+            tracing: false,
+            trace: vec![],
+            int_space: true,
+            heatmap: false,
+            visit_counts: HashMap::new(),
+        }
+    }
+
+    // A leading `#!/` or `#! ` line is a shebang rather than code, so it is
+    // dropped and the program starts on the line after it. Any other `#!` is
+    // just a trampoline over `!`.
+    pub fn from_source(source: &str, input_reader: Box<dyn InputReader>) -> Program {
+        Program::from_source_with_operator_map(source, input_reader, default_operator_map())
+    }
+
+    // Reads the source, and anything `p` and `s` write, with a remapped set of
+    // operator characters
+    pub fn from_source_with_operator_map(
+        source: &str,
+        input_reader: Box<dyn InputReader>,
+        operator_map: OperatorMap,
+    ) -> Program {
+        let source = if source.starts_with("#!/") || source.starts_with("#! ") {
+            source.split_once('\n').map(|(_, rest)| rest).unwrap_or("")
+        } else {
+            source
+        };
+
+        let grid = lines_to_token_matrix_with(&operator_map, source.lines());
+        let mut program = Program::new(grid, input_reader);
+        program.operator_map = operator_map;
+        program
+    }
+
+    fn token_to_char(&self, token: &Token) -> char {
+        token_to_char_with(&self.operator_map, token)
+    }
+
+    fn char_to_token(&self, character: char) -> Token {
+        char_to_token_with(&self.operator_map, character)
+    }
+
+    // What `g` reads from a cell, and the cell `p` writes for a value
+    fn token_to_value(&self, token: &Token) -> i32 {
+        match token {
+            Token::Value(value) => *value,
+            token               => self.token_to_char(token) as i32,
+        }
+    }
+
+    fn value_to_token(&self, value: i32) -> Token {
+        match char::from_u32(value as u32) {
+            Some(character) => self.char_to_token(character),
+            None            => Token::Value(value),
+        }
+    }
+
+    pub fn set_version(&mut self, version: FungeVersion) {
+        self.version = version;
+    }
+
+    pub fn version(&self) -> FungeVersion {
+        self.version
+    }
+
+    // Resizes the Befunge-93 playfield for variants that don't use 80x25. Each
+    // dimension is at least one cell.
+    pub fn set_field_size(&mut self, width: i32, height: i32) {
+        self.field = (width.max(1), height.max(1));
+    }
+
+    // Without wrapping, a pointer stepping off any edge halts the program
+    // rather than coming back round the other side
+    pub fn set_wrapping(&mut self, wrapping: bool) {
+        self.wrapping = wrapping;
+    }
+
+    // Pads every row out to the full width, now and whenever `p` widens the
+    // grid, so it is always a rectangle rather than a set of jagged rows
+    pub fn set_pad_grid(&mut self, pad_grid: bool) {
+        self.pad_grid = pad_grid;
+        if pad_grid {
+            pad_rows(&mut self.grid, self.width);
+            let initial_width = grid_width(&self.initial_grid);
+            pad_rows(&mut self.initial_grid, initial_width);
+        }
+    }
+
+    pub fn set_max_steps(&mut self, limit: u64) {
+        self.max_steps = Some(limit);
+    }
+
+    pub fn max_steps(&self) -> Option<u64> {
+        self.max_steps
+    }
+
+    // Halts with LikelyInfiniteLoop when the pointer, its direction and the
+    // stacks all come back to a state seen in the last LOOP_HISTORY steps.
+    // Off by default since it hashes the whole stack every step.
+    pub fn set_loop_detection(&mut self, loop_detection: bool) {
+        self.loop_detection = loop_detection;
+        self.recent_states.clear();
+        self.recent_state_set.clear();
+    }
+
+    // Only characters without a meaning of their own can be bound, so an
+    // extension never shadows a real instruction
+    pub fn set_extension_operator(&mut self, character: char, operator: ExtensionOperator) -> Result<(), String> {
+        if self.char_to_token(character) != Token::Char(character) {
+            return Err(format!("'{}' is already an instruction", character));
+        }
+
+        self.extension_operators.insert(character, operator);
+        Ok(())
+    }
+
+    pub fn set_random_strategy(&mut self, strategy: RandomStrategy) {
+        self.round_robin = strategy == RandomStrategy::RoundRobin;
+        self.round_robin_index = 0;
+
+        match strategy {
+            RandomStrategy::True         => self.rng = Box::new(rand::thread_rng()),
+            RandomStrategy::Seeded(seed) => self.rng = Box::new(StdRng::seed_from_u64(seed)),
+            RandomStrategy::RoundRobin   => {},
+        }
+    }
+
+    // Pushing past the limit halts the program instead of growing the stack
+    pub fn set_max_stack_depth(&mut self, limit: usize) {
+        self.max_stack_depth = Some(limit);
+    }
+
+    pub fn max_stack_depth(&self) -> Option<usize> {
+        self.max_stack_depth
+    }
+
+    // Strict mode still pops a 0 from an empty stack, but leaves a warning
+    // behind so the underflow can be tracked down
+    pub fn set_strict_stack(&mut self, strict: bool) {
+        self.strict_stack = strict;
+    }
+
+    // The trailing space after `.` is standard, but noisy when a program only
+    // prints a single number
+    pub fn set_int_space(&mut self, int_space: bool) {
+        self.int_space = int_space;
+    }
+
+    // Makes `,` emit values 0-255 as single raw bytes rather than encoding them
+    // as UTF-8, so byte-oriented programs like cat stay byte-faithful
+    pub fn set_byte_output(&mut self, byte_output: bool) {
+        self.byte_output = byte_output;
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<RuntimeWarning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // Counts every token dispatched, including ones pushed in string mode
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiling = profiling;
+    }
+
+    pub fn operator_counts(&self) -> &HashMap<Token, u64> {
+        &self.operator_counts
+    }
+
+    pub fn set_heatmap(&mut self, heatmap: bool) {
+        self.heatmap = heatmap;
+    }
+
+    // How many steps have started on the cell at grid coordinates (x, y)
+    pub fn visit_count(&self, x: i32, y: i32) -> u64 {
+        *self.visit_counts.get(&(x - self.origin_x, y - self.origin_y)).unwrap_or(&0)
+    }
+
+    pub fn max_visit_count(&self) -> u64 {
+        self.visit_counts.values().cloned().max().unwrap_or(0)
+    }
+
+    pub fn set_tracing(&mut self, tracing: bool) {
+        self.tracing = tracing;
+    }
+
+    pub fn take_trace(&mut self) -> Vec<TraceEntry> {
+        std::mem::take(&mut self.trace)
+    }
+
+    // Steps the program through the trace, stopping at the first step whose
+    // cell or direction differs. Halting early counts as a mismatch too.
+    pub fn verify_against_trace(&mut self, trace: &[TraceEntry]) -> Result<(), TraceMismatch> {
+        for (step, entry) in trace.iter().enumerate() {
+            let actual = (self.xptr, self.yptr);
+
+            if !self.is_running || actual != (entry.x, entry.y) || self.delta != entry.direction {
+                return Err(TraceMismatch {
+                    step,
+                    expected: (entry.x, entry.y),
+                    actual,
+                    expected_direction: entry.direction,
+                    actual_direction: self.delta,
+                });
+            }
+
+            self.step();
+        }
+
+        Ok(())
+    }
+
+    // Keyed relative to the origin so the counts stay put when `p` grows the
+    // grid up or left
+    fn record_visit(&mut self) {
+        if self.heatmap {
+            let position = (self.xptr - self.origin_x, self.yptr - self.origin_y);
+            *self.visit_counts.entry(position).or_insert(0) += 1;
+        }
+    }
+
+    fn state_fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (self.xptr, self.yptr, self.delta, self.string_mode).hash(&mut hasher);
+        (&self.stack, &self.stack_stack, self.storage_offset).hash(&mut hasher);
+        (self.round_robin_index, self.side_effects, &self.other_ips).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Whether the program is back in a state it was in recently
+    fn revisits_recent_state(&mut self) -> bool {
+        let fingerprint = self.state_fingerprint();
+        if !self.recent_state_set.insert(fingerprint) {
+            return true;
+        }
+
+        self.recent_states.push_back(fingerprint);
+        if self.recent_states.len() > LOOP_HISTORY {
+            if let Some(oldest) = self.recent_states.pop_front() {
+                self.recent_state_set.remove(&oldest);
+            }
+        }
+        false
+    }
+
+    fn record_trace_entry(&mut self, token: Token) {
+        if self.tracing {
+            self.trace.push(TraceEntry {
+                step: self.instruction_count,
+                x: self.xptr,
+                y: self.yptr,
+                direction: self.delta,
+                op: self.token_to_char(&token),
+                stack_len: self.stack.len(),
+            });
+        }
+    }
+
+    fn record_operator(&mut self, token: Token) {
+        if self.profiling {
+            *self.operator_counts.entry(token).or_insert(0) += 1;
+        }
+    }
+
+    // Only the first underflow of an instruction is reported, so `+` on an
+    // empty stack is one warning rather than two
+    fn warn_underflow(&mut self) {
+        if self.strict_stack && !self.underflowed_this_step {
+            self.underflowed_this_step = true;
+            let operator = self.get_token(self.xptr, self.yptr).unwrap_or(Token::Noop);
+            self.warnings.push(RuntimeWarning::StackUnderflow {
+                x: self.xptr,
+                y: self.yptr,
+                operator: self.token_to_char(&operator),
+            });
         }
     }
 
     fn stack_pop(&mut self) -> i32 {
         match self.stack.pop() {
             Some(value) => value,
-            None        => 0
+            None        => {
+                self.warn_underflow();
+                0
+            },
+        }
+    }
+
+    // Whether a stack holding `len` values has room for `count` more. When it
+    // doesn't the program halts, so block moves hit the same limit as pushes.
+    fn check_stack_room(&mut self, len: usize, count: usize) -> bool {
+        match self.max_stack_depth {
+            Some(max_stack_depth) if len.saturating_add(count) > max_stack_depth => {
+                self.halt(HaltReason::StackOverflow);
+                false
+            },
+            _ => true,
         }
     }
 
     fn stack_push(&mut self, value: i32) {
-        self.stack.push(value);
+        if self.check_stack_room(self.stack.len(), 1) {
+            self.stack.push(value);
+        }
     }
 
-    fn stack_peek(&self) -> i32 {
+    fn stack_peek(&mut self) -> i32 {
         match self.stack.last() {
             Some(value) => *value,
-            None        => 0,
+            None        => {
+                self.warn_underflow();
+                0
+            },
         }
     }
 
+    // `a` is popped first, so it is the top of the stack and `b` the value
+    // pushed before it
     fn binary_stack_op_push<F>(&mut self, op: F) where F: Fn(i32, i32) -> i32 {
         let a = self.stack_pop();
         let b = self.stack_pop();
-        self.stack.push(op(a, b))
+        self.stack_push(op(a, b))
     }
 
+    // In Befunge-93 the torus is always exactly the field size (80x25 unless
+    // set otherwise), whatever the size of the source, with anything past the
+    // edge unreachable; in Befunge-98 it is the bounding box of every cell the
+    // grid has held. `p` can grow that box but never shrinks it, even when it
+    // blanks the outermost cell, so the wrap points stay put as a program
+    // modifies itself.
     pub fn height(&self) -> i32 {
-        self.grid.len() as i32
+        match self.version {
+            FungeVersion::Befunge93 => self.field.1,
+            FungeVersion::Befunge98 => self.grid.len() as i32,
+        }
     }
 
     pub fn width(&self) -> i32 {
-        self.width
+        match self.version {
+            FungeVersion::Befunge93 => self.field.0,
+            FungeVersion::Befunge98 => self.width,
+        }
     }
 
-    pub fn step(&mut self) {
+    pub fn step(&mut self) -> StepResult {
         self.last_output = String::new();
+        self.last_output_bytes.clear();
+        self.underflowed_this_step = false;
+        self.turned_this_step = false;
 
-        let current_token = self.get_token(self.xptr, self.yptr).unwrap();
+        let current_token = match self.get_token(self.xptr, self.yptr) {
+            Some(token) => token,
+            None        => {
+                let error = RuntimeError::PointerOutOfBounds { x: self.xptr, y: self.yptr };
+                self.halt(HaltReason::Error(error));
+                return StepResult::Error(error);
+            },
+        };
+        if self.loop_detection && self.revisits_recent_state() {
+            self.halt(HaltReason::LikelyInfiniteLoop);
+            return StepResult::Halted;
+        }
+        self.record_visit();
+        self.record_trace_entry(current_token);
+        let delta = self.delta;
         if self.string_mode {
             self.perform_string_action(current_token);
         } else {
             self.perform_action(current_token);
         }
+        self.turned_this_step = self.delta != delta;
         self.move_program_pointer();
+        self.next_ip();
+
+        self.instruction_count += 1;
+        if let Some(max_steps) = self.max_steps {
+            if self.is_running && self.instruction_count >= max_steps {
+                self.halt(HaltReason::StepLimit);
+            }
+        }
+
+        if self.is_running {
+            StepResult::Running
+        } else {
+            StepResult::Halted
+        }
     }
 
-    fn set_token(&mut self, x: i32, y: i32, token: Token) {
-        if x < 0 || y < 0  { // TODO: Be fancy and add rows/columns to the top/left
-            panic!("Tried setting a negative value on the grid");
+    // Steps like step(), and reports what the instruction did
+    pub fn step_observed(&mut self) -> StepObservation {
+        let (x, y) = (self.xptr, self.yptr);
+        let token = self.current_token();
+        let grid_writes = self.grid_writes;
+
+        let result = self.step();
+
+        StepObservation {
+            result,
+            x,
+            y,
+            token,
+            produced_output: !self.last_output_bytes.is_empty(),
+            changed_direction: self.turned_this_step,
+            modified_grid: self.grid_writes != grid_writes,
         }
+    }
 
-        if x > self.width() {
-            self.width = x;
+    fn take_ip(&mut self) -> InstructionPointer {
+        InstructionPointer {
+            xptr: self.xptr,
+            yptr: self.yptr,
+            delta: self.delta,
+            string_mode: self.string_mode,
+            stack: std::mem::take(&mut self.stack),
+            stack_stack: std::mem::take(&mut self.stack_stack),
+            storage_offset: self.storage_offset,
         }
+    }
 
-        let x = x as usize;
-        let y = y as usize;
+    fn load_ip(&mut self, ip: InstructionPointer) {
+        self.xptr = ip.xptr;
+        self.yptr = ip.yptr;
+        self.delta = ip.delta;
+        self.string_mode = ip.string_mode;
+        self.stack = ip.stack;
+        self.stack_stack = ip.stack_stack;
+        self.storage_offset = ip.storage_offset;
+    }
 
-        // Expand the height to allow inserting at coordinate y
-        for _ in self.grid.len()..=y {
-            self.grid.push(vec![]);
+    // Hands the next step to the pointer whose turn it is, dropping the
+    // running one if `@` ended it
+    fn next_ip(&mut self) {
+        if !self.is_running {
+            return;
         }
 
-        // Expand row with no-ops to make room for the new token
-        for _ in self.grid[y].len()..=x {
-            self.grid[y].push(Token::Noop);
+        if std::mem::take(&mut self.ip_ended) {
+            if let Some(next) = self.other_ips.pop_front() {
+                self.load_ip(next);
+            }
+        } else if let Some(next) = self.other_ips.pop_front() {
+            let current = self.take_ip();
+            self.other_ips.push_back(current);
+            self.load_ip(next);
         }
+    }
 
-        self.grid[y][x] = token;
+    // Funge-98 `t`: the copy heads the opposite way from one cell behind the
+    // `t` and gets the very next turn. A copy that would step straight off a
+    // non-wrapping grid is never created.
+    fn split(&mut self) {
+        let delta = (-self.delta.0, -self.delta.1);
+        if let Some((xptr, yptr)) = self.next_position(self.xptr, self.yptr, delta) {
+            self.other_ips.push_front(InstructionPointer {
+                xptr,
+                yptr,
+                delta,
+                string_mode: false,
+                stack: self.stack.clone(),
+                stack_stack: self.stack_stack.clone(),
+                storage_offset: self.storage_offset,
+            });
+        }
     }
 
-    fn get_token(&self, x: i32, y: i32) -> Option<Token> {
-        if x < 0 || y < 0 || x >= self.width() || y >= self.height() {
-            return None;
+    // How many instruction pointers are alive, which is only ever more than
+    // one after a Funge-98 `t`
+    pub fn ip_count(&self) -> usize {
+        self.other_ips.len() + 1
+    }
+
+    fn halt(&mut self, reason: HaltReason) {
+        self.is_running = false;
+        self.halt_reason = Some(reason);
+    }
+
+    pub fn snapshot(&self) -> ProgramState {
+        ProgramState {
+            xptr: self.xptr,
+            yptr: self.yptr,
+            delta: self.delta,
+            grid: self.grid.clone(),
+            stack: self.stack.clone(),
+            stack_stack: self.stack_stack.clone(),
+            storage_offset: self.storage_offset,
+            is_running: self.is_running,
+            halt_reason: self.halt_reason,
+            string_mode: self.string_mode,
+            width: self.width,
+            origin_x: self.origin_x,
+            origin_y: self.origin_y,
+            instruction_count: self.instruction_count,
+            version: self.version,
+            other_ips: self.other_ips.clone(),
         }
+    }
 
-        let x = x as usize;
-        let y = y as usize;
+    pub fn restore(&mut self, state: ProgramState) {
+        self.xptr = state.xptr;
+        self.yptr = state.yptr;
+        self.delta = state.delta;
+        self.grid = state.grid;
+        self.stack = state.stack;
+        self.stack_stack = state.stack_stack;
+        self.storage_offset = state.storage_offset;
+        self.is_running = state.is_running;
+        self.halt_reason = state.halt_reason;
+        self.string_mode = state.string_mode;
+        self.width = state.width;
+        self.origin_x = state.origin_x;
+        self.origin_y = state.origin_y;
+        self.instruction_count = state.instruction_count;
+        self.version = state.version;
+        self.other_ips = state.other_ips;
+        self.ip_ended = false;
+        // Going back to an earlier state on purpose isn't a loop
+        self.recent_states.clear();
+        self.recent_state_set.clear();
+    }
 
-        Some(match self.grid.get(y) {
-            Some(row) => match row.get(x) {
-                Some(token) => token.clone(),
-                None => Token::Noop,
-            },
-            // Default to Noop to give the illusion of a grid
-            None => Token::Noop,
-        })
+    #[cfg(feature = "serde")]
+    pub fn save_checkpoint<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = io::BufWriter::new(File::create(path)?);
+        serde_json::to_writer(file, &self.snapshot())?;
+        Ok(())
     }
 
-    fn move_program_pointer(&mut self) {
-        let max_y = self.grid.len() as i32;
-        let max_x = self.grid[self.yptr as usize].len() as i32;
-
-        match self.direction {
-            Direction::Up    => self.yptr = decrement_wrap(self.yptr, max_y),
-            Direction::Down  => self.yptr = increment_wrap(self.yptr, max_y),
-            Direction::Left  => self.xptr = decrement_wrap(self.xptr, max_x),
-            Direction::Right => self.xptr = increment_wrap(self.xptr, max_x),
-        };
+    // The input reader can't be saved, so the resumed program reads from a
+    // fresh one. reset() starts over on the checkpointed grid.
+    #[cfg(feature = "serde")]
+    pub fn load_checkpoint<P: AsRef<Path>>(path: P, input_reader: Box<dyn InputReader>) -> io::Result<Program> {
+        let file = io::BufReader::new(File::open(path)?);
+        let state: ProgramState = serde_json::from_reader(file)?;
+
+        let mut program = Program::new(state.grid.clone(), input_reader);
+        program.restore(state);
+        Ok(program)
     }
 
-    fn perform_action(&mut self, action: Token) {
-        match action {
-            Token::Add          => self.binary_stack_op_push(|a, b| a + b),
-            Token::Subtract     => self.binary_stack_op_push(|a, b| b - a),
-            Token::Multiply     => self.binary_stack_op_push(|a, b| a * b),
-            Token::Divide       => {
-                let a = self.stack_pop();
-                let b = self.stack_pop();
-
-                self.stack.push(
-                    if a == 0 {
-                        self.input_reader.read_int()
-                    } else {
-                        b / a
-                    }
-                );
+    // Puts the program back the way it was loaded, undoing any self-modification,
+    // while keeping its configuration and input reader
+    pub fn reset(&mut self) {
+        self.xptr = self.start_position.0;
+        self.yptr = self.start_position.1;
+        self.delta = self.start_delta;
+        self.grid = self.initial_grid.clone();
+        self.width = grid_width(&self.grid);
+        self.origin_x = 0;
+        self.origin_y = 0;
+        self.stack = self.initial_stack.clone();
+        self.stack_stack.clear();
+        self.storage_offset = (0, 0);
+        self.is_running = true;
+        self.halt_reason = None;
+        self.string_mode = false;
+        self.last_output = String::new();
+        self.last_output_bytes.clear();
+        self.round_robin_index = 0;
+        self.quit_dispatched = false;
+        self.exit_code = None;
+        self.instruction_count = 0;
+        self.recent_states.clear();
+        self.recent_state_set.clear();
+        self.side_effects = 0;
+        self.grid_writes = 0;
+        self.other_ips.clear();
+        self.ip_ended = false;
+    }
+
+    // Moves the pointer before the first step, which is handy for running a
+    // fragment of a larger program. reset() returns here too.
+    pub fn set_start(&mut self, x: i32, y: i32, direction: Direction) -> Result<(), RuntimeError> {
+        if self.get_token(x, y).is_none() {
+            return Err(RuntimeError::PointerOutOfBounds { x, y });
+        }
+
+        self.start_position = (x, y);
+        self.start_delta = direction.delta();
+        self.xptr = x;
+        self.yptr = y;
+        self.delta = self.start_delta;
+        Ok(())
+    }
+
+    // Pushes the values before the first step, bottom first, so the last value
+    // is the top of the stack. reset() puts them back too.
+    pub fn set_initial_stack(&mut self, values: Vec<i32>) {
+        self.stack = values.clone();
+        self.initial_stack = values;
+    }
+
+    pub fn set_input_reader(&mut self, input_reader: Box<dyn InputReader>) {
+        self.input_reader = input_reader;
+    }
+
+    // Steps once and hands back whatever that step printed, or None once the
+    // program has halted
+    pub fn next_output(&mut self) -> Option<String> {
+        if !self.is_running {
+            return None;
+        }
+
+        self.step();
+        Some(self.get_last_output())
+    }
+
+    pub fn run_to_completion(&mut self) -> String {
+        let mut output = String::new();
+
+        while self.is_running() {
+            self.step();
+            output += &self.last_output;
+        }
+
+        output
+    }
+
+    pub fn run_with_limit(&mut self, limit: u64) -> (String, HaltReason) {
+        self.set_max_steps(limit);
+        let output = self.run_to_completion();
+
+        (output, self.halt_reason.unwrap_or(HaltReason::Quit))
+    }
+
+    fn set_token(&mut self, x: i32, y: i32, token: Token) {
+        self.side_effects += 1;
+        self.grid_writes += 1;
+
+        // Writes that would stretch the grid past MAX_GRID_EXTENT are dropped,
+        // like puts outside the Befunge-93 field
+        let span = |coordinate: i32, size: i32| {
+            let coordinate = coordinate as i64;
+            coordinate.max(size as i64 - 1) - coordinate.min(0) + 1
+        };
+        if span(x, self.width) > MAX_GRID_EXTENT || span(y, self.height()) > MAX_GRID_EXTENT {
+            return;
+        }
+
+        let x = if x < 0 { self.grow_left(x.unsigned_abs() as i32) } else { x };
+        let y = if y < 0 { self.grow_up(y.unsigned_abs() as i32) } else { y };
+
+        if x >= self.width {
+            self.width = x + 1;
+        }
+
+        let x = x as usize;
+        let y = y as usize;
+
+        // Expand the height to allow inserting at coordinate y
+        for _ in self.grid.len()..=y {
+            self.grid.push(vec![]);
+        }
+
+        // Expand row with no-ops to make room for the new token
+        for _ in self.grid[y].len()..=x {
+            self.grid[y].push(Token::Noop);
+        }
+
+        self.grid[y][x] = token;
+
+        if self.pad_grid {
+            pad_rows(&mut self.grid, self.width);
+        }
+    }
+
+    // Prepends `columns` empty columns, returning the grid x of the leftmost one
+    fn grow_left(&mut self, columns: i32) -> i32 {
+        for row in self.grid.iter_mut() {
+            row.splice(0..0, std::iter::repeat_n(Token::Noop, columns as usize));
+        }
+
+        self.width += columns;
+        self.origin_x += columns;
+        self.xptr += columns;
+        for ip in self.other_ips.iter_mut() {
+            ip.xptr += columns;
+        }
+        0
+    }
+
+    // Prepends `rows` empty rows, returning the grid y of the topmost one
+    fn grow_up(&mut self, rows: i32) -> i32 {
+        self.grid.splice(0..0, (0..rows).map(|_| vec![]));
+
+        self.origin_y += rows;
+        self.yptr += rows;
+        for ip in self.other_ips.iter_mut() {
+            ip.yptr += rows;
+        }
+        0
+    }
+
+    // Where a `g` or `p` coordinate lands in the grid, or None when it's
+    // outside the playfield or too far away to address at all
+    fn grid_position(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        if !self.version.in_playfield(x, y, self.field) {
+            return None;
+        }
+
+        Some((x.checked_add(self.origin_x)?, y.checked_add(self.origin_y)?))
+    }
+
+    fn get_token(&self, x: i32, y: i32) -> Option<Token> {
+        if x < 0 || y < 0 || x >= self.width() || y >= self.height() {
+            return None;
+        }
+
+        let x = x as usize;
+        let y = y as usize;
+
+        Some(match self.grid.get(y) {
+            Some(row) => match row.get(x) {
+                Some(token) => *token,
+                None => Token::Noop,
             },
-            Token::Modulo       => self.binary_stack_op_push(|a, b| b % a),
+            // Default to Noop to give the illusion of a grid
+            None => Token::Noop,
+        })
+    }
+
+    // Wrap against the whole torus rather than the current row, so jagged
+    // rows behave as if they were padded out with spaces. None when the move
+    // leaves a grid that doesn't wrap.
+    fn next_position(&self, x: i32, y: i32, delta: (i32, i32)) -> Option<(i32, i32)> {
+        let (x, y) = (x as i64 + delta.0 as i64, y as i64 + delta.1 as i64);
+
+        if !self.wrapping && (x < 0 || y < 0 || x >= self.width() as i64 || y >= self.height() as i64) {
+            return None;
+        }
+
+        Some((wrap(x, self.width()), wrap(y, self.height())))
+    }
+
+    fn move_program_pointer(&mut self) {
+        match self.next_position(self.xptr, self.yptr, self.delta) {
+            Some((x, y)) => {
+                self.xptr = x;
+                self.yptr = y;
+            },
+            None => self.halt(HaltReason::LeftGrid),
+        }
+    }
+
+    fn set_direction(&mut self, direction: Direction) {
+        self.delta = direction.delta();
+    }
+
+    fn perform_action(&mut self, action: Token) {
+        self.record_operator(action);
+
+        if !self.version.supports(action) {
+            return;
+        }
+
+        match action {
+            // As in the Befunge-93 spec the operands read left to right in the
+            // source: `73-` is 7 - 3, `73/` is 7 / 3, `73%` is 7 % 3, and 73
+            // followed by a backtick is 7 > 3
+            //
+            // Arithmetic wraps on overflow, so debug and release builds agree
+            Token::Add          => self.binary_stack_op_push(|a, b| b.wrapping_add(a)),
+            Token::Subtract     => self.binary_stack_op_push(|a, b| b.wrapping_sub(a)),
+            Token::Multiply     => self.binary_stack_op_push(|a, b| b.wrapping_mul(a)),
+            // Dividing by zero pushes 0 rather than trapping
+            Token::Divide       => self.binary_stack_op_push(|a, b| if a == 0 { 0 } else { b.wrapping_div(a) }),
+            Token::Modulo       => self.binary_stack_op_push(|a, b| if a == 0 { 0 } else { b.wrapping_rem(a) }),
+            // Any non-zero value, negative ones included, counts as true
             Token::Not          => {
                 let stack_val = self.stack_pop();
                 self.stack_push(if stack_val == 0 { 1 } else { 0 });
             },
             Token::Greater      => self.binary_stack_op_push(|a, b| if b > a { 1 } else { 0 }),
-            Token::Right        => self.direction = Direction::Right,
-            Token::Left         => self.direction = Direction::Left,
-            Token::Up           => self.direction = Direction::Up,
-            Token::Down         => self.direction = Direction::Down,
-            Token::Random       => self.direction = rand::random(),
+            Token::Right        => self.set_direction(Direction::Right),
+            Token::Left         => self.set_direction(Direction::Left),
+            Token::Up           => self.set_direction(Direction::Up),
+            Token::Down         => self.set_direction(Direction::Down),
+            Token::Random       => {
+                let direction = if self.round_robin {
+                    let direction = ROUND_ROBIN_DIRECTIONS[self.round_robin_index];
+                    self.round_robin_index = (self.round_robin_index + 1) % ROUND_ROBIN_DIRECTIONS.len();
+                    direction
+                } else {
+                    self.side_effects += 1;
+                    self.rng.gen()
+                };
+                self.set_direction(direction);
+            },
             Token::HorizontalIf => {
-                self.direction = if self.stack_pop() == 0 {
+                let direction = if self.stack_pop() == 0 {
                     Direction::Right
                 } else {
                     Direction::Left
-                }
+                };
+                self.set_direction(direction);
             },
             Token::VerticalIf   => {
-                self.direction = if self.stack_pop() == 0 {
+                let direction = if self.stack_pop() == 0 {
                     Direction::Down
                 } else {
                     Direction::Up
-                }
+                };
+                self.set_direction(direction);
             },
             Token::StringMode   => self.string_mode = true,
-            Token::Duplicate    => self.stack_push(self.stack_peek()),
+            Token::Duplicate    => {
+                let top = self.stack_peek();
+                self.stack_push(top);
+            },
             Token::Swap         => {
                 let top = self.stack_pop();
                 let bottom = self.stack_pop();
@@ -343,54 +1323,304 @@ impl Program {
                 self.stack_push(bottom);
             },
             Token::Discard      => { self.stack_pop(); },
-            Token::PrintInt     => self.last_output = format!("{} ", self.stack_pop()),
-            Token::PrintChar    => self.last_output = format!("{}", i32_to_char(self.stack_pop())),
+            Token::PrintInt     => {
+                let value = self.stack_pop();
+                let text = if self.int_space {
+                    format!("{} ", value)
+                } else {
+                    value.to_string()
+                };
+                self.last_output_bytes.extend_from_slice(text.as_bytes());
+                self.last_output += &text;
+            },
+            Token::PrintChar    => {
+                let value = self.stack_pop();
+                let c = i32_to_char(value);
+                if self.byte_output && (0..=255).contains(&value) {
+                    self.last_output_bytes.push(value as u8);
+                } else {
+                    self.last_output_bytes.extend_from_slice(c.to_string().as_bytes());
+                }
+                self.last_output.push(c);
+            },
             Token::Bridge       => self.move_program_pointer(),
             Token::Get          => {
-                let y = self.stack_pop();
-                let x = self.stack_pop();
-                self.stack_push(match self.get_token(x, y) {
-                    Some(token) => token_to_char(&token) as i32,
+                let y = self.stack_pop().wrapping_add(self.storage_offset.1);
+                let x = self.stack_pop().wrapping_add(self.storage_offset.0);
+                let token = match self.grid_position(x, y) {
+                    Some((x, y)) => self.get_token(x, y),
+                    None         => None,
+                };
+                self.stack_push(match token {
+                    Some(token) => self.token_to_value(&token),
                     None        => 0,
                 });
             },
             Token::Put          => {
-                let y = self.stack_pop();
-                let x = self.stack_pop();
+                let y = self.stack_pop().wrapping_add(self.storage_offset.1);
+                let x = self.stack_pop().wrapping_add(self.storage_offset.0);
                 let v = self.stack_pop();
-                self.set_token(x, y, char_to_token(i32_to_char(v)));
+                if let Some((x, y)) = self.grid_position(x, y) {
+                    let token = self.value_to_token(v);
+                    self.set_token(x, y, token);
+                }
             },
             Token::ReadInt      => {
+                self.side_effects += 1;
                 let int = self.input_reader.read_int();
                 self.stack_push(int);
             },
             Token::ReadChar     => {
+                self.side_effects += 1;
                 let character = self.input_reader.read_char();
                 self.stack_push(character);
             },
-            Token::Quit         => self.is_running = false,
-            Token::Int(value)   => self.stack.push(value as i32),
+            Token::Quit         => {
+                // Only the running pointer ends while any others remain
+                self.quit_dispatched = true;
+                if self.other_ips.is_empty() {
+                    self.halt(HaltReason::Quit);
+                } else {
+                    self.ip_ended = true;
+                }
+            },
+            Token::QuitWithCode => {
+                self.exit_code = Some(self.stack_pop());
+                self.quit_dispatched = true;
+                self.halt(HaltReason::Quit);
+            },
+            Token::FetchChar    => {
+                // Push the next cell and hop over it so it isn't executed
+                self.move_program_pointer();
+                let token = self.get_token(self.xptr, self.yptr).unwrap_or(Token::Noop);
+                self.stack_push(self.token_to_value(&token));
+            },
+            Token::StoreChar    => {
+                let v = self.stack_pop();
+                self.move_program_pointer();
+                let token = self.value_to_token(v);
+                self.set_token(self.xptr, self.yptr, token);
+            },
+            Token::Iterate      => {
+                let count = self.stack_pop();
+                let (x, y) = (self.xptr, self.yptr);
+                self.move_program_pointer();
+                let (next_x, next_y) = (self.xptr, self.yptr);
+                let next_token = self.get_token(next_x, next_y).unwrap_or(Token::Noop);
+
+                // The iterated instruction runs from k's cell, and the pointer
+                // then carries on from the instruction's cell so it isn't run
+                // again (0k simply skips it). An iterated `k` is a no-op
+                // rather than a nested repeat, and the repeats stop as soon as
+                // the program halts or would run past its step limit.
+                self.xptr = x;
+                self.yptr = y;
+                if next_token != Token::Iterate && next_token != Token::Noop {
+                    for repeat in 0..count.max(0) as u64 {
+                        if !self.is_running {
+                            break;
+                        }
+                        if let Some(max_steps) = self.max_steps {
+                            if self.instruction_count + repeat >= max_steps {
+                                self.halt(HaltReason::StepLimit);
+                                break;
+                            }
+                        }
+                        self.perform_action(next_token);
+                    }
+                }
+                self.xptr = next_x;
+                self.yptr = next_y;
+            },
+            Token::SetVector    => {
+                let dy = self.stack_pop();
+                let dx = self.stack_pop();
+                self.delta = (dx, dy);
+            },
+            Token::Reverse      => self.reflect(),
+            // Rotations are on screen, where y grows downwards
+            Token::TurnLeft     => self.delta = (self.delta.1, -self.delta.0),
+            Token::TurnRight    => self.delta = (-self.delta.1, self.delta.0),
+            Token::ClearStack   => self.stack.clear(),
+            Token::BeginBlock   => self.begin_block(),
+            Token::EndBlock     => self.end_block(),
+            Token::StackUnderStack => self.stack_under_stack(),
+            Token::Split        => self.split(),
+            Token::Int(value)   => self.stack_push(value),
             Token::Noop         => {}, // Do nothing
-            Token::Char(_)      => {}, // Do nothing
+            Token::Value(_)     => {},
+            Token::Char(c)      => {
+                if let Some(operator) = self.extension_operators.get(&c).cloned() {
+                    self.perform_extension(operator);
+                }
+            },
+        };
+    }
+
+    fn perform_extension(&mut self, operator: ExtensionOperator) {
+        match operator {
+            ExtensionOperator::PushPosition => {
+                self.stack_push(self.yptr - self.origin_y);
+                self.stack_push(self.xptr - self.origin_x);
+            },
+        }
+    }
+
+    fn reflect(&mut self) {
+        self.delta = (-self.delta.0, -self.delta.1);
+    }
+
+    // Moves the top `count` values of `from` onto `to`, keeping their order and
+    // making up any shortfall with zeros underneath
+    fn transfer_block(from: &mut Vec<i32>, to: &mut Vec<i32>, count: usize) {
+        let available = from.len().min(count);
+        to.extend(std::iter::repeat_n(0, count - available));
+        to.extend(from.drain(from.len() - available..));
+    }
+
+    // `{` opens a new stack carrying the top n values, saving the storage
+    // offset underneath so `}` can put it back
+    fn begin_block(&mut self) {
+        let count = self.stack_pop();
+        let mut toss = vec![];
+
+        // The new stack gets the top n values, and the one left underneath
+        // gains the two offset values plus the zeros for a negative count
+        let moved = self.stack.len().min(count.max(0) as usize);
+        let soss_gained = if count > 0 { 2 } else { count.unsigned_abs() as usize + 2 };
+        if !self.check_stack_room(0, count.max(0) as usize)
+            || !self.check_stack_room(self.stack.len() - moved, soss_gained) {
+            return;
+        }
+
+        if count > 0 {
+            Program::transfer_block(&mut self.stack, &mut toss, count as usize);
+        } else {
+            self.stack.extend(std::iter::repeat_n(0, count.unsigned_abs() as usize));
+        }
+
+        self.stack.push(self.storage_offset.0);
+        self.stack.push(self.storage_offset.1);
+        self.storage_offset = (
+            self.xptr.wrapping_sub(self.origin_x).wrapping_add(self.delta.0),
+            self.yptr.wrapping_sub(self.origin_y).wrapping_add(self.delta.1),
+        );
+
+        let soss = std::mem::replace(&mut self.stack, toss);
+        self.stack_stack.push(soss);
+    }
+
+    // `}` closes the current stack, handing its top n values back to the one
+    // below. With nothing to close it reflects like `r`.
+    fn end_block(&mut self) {
+        let soss_len = match self.stack_stack.last() {
+            Some(soss) => soss.len().saturating_sub(2),
+            None       => return self.reflect(),
+        };
+
+        let count = self.stack_pop();
+        if !self.check_stack_room(soss_len, count.max(0) as usize) {
+            return;
+        }
+
+        let mut soss = self.stack_stack.pop().unwrap();
+        let y = soss.pop().unwrap_or(0);
+        let x = soss.pop().unwrap_or(0);
+        self.storage_offset = (x, y);
+
+        if count > 0 {
+            Program::transfer_block(&mut self.stack, &mut soss, count as usize);
+        } else {
+            soss.truncate(soss.len().saturating_sub(count.unsigned_abs() as usize));
+        }
+
+        self.stack = soss;
+    }
+
+    // `u` pops values one at a time from the stack below onto the current one,
+    // or the other way round for a negative count
+    fn stack_under_stack(&mut self) {
+        if self.stack_stack.is_empty() {
+            return self.reflect();
+        }
+
+        let count = self.stack_pop();
+        let receiving_len = if count > 0 {
+            self.stack.len()
+        } else {
+            self.stack_stack.last().map_or(0, |soss| soss.len())
         };
+        if !self.check_stack_room(receiving_len, count.unsigned_abs() as usize) {
+            return;
+        }
+
+        for _ in 0..count.unsigned_abs() {
+            if count > 0 {
+                let value = self.stack_stack.last_mut().and_then(|soss| soss.pop()).unwrap_or(0);
+                self.stack_push(value);
+            } else {
+                let value = self.stack_pop();
+                self.stack_stack.last_mut().unwrap().push(value);
+            }
+        }
     }
 
     fn perform_string_action(&mut self, action: Token) {
+        self.record_operator(action);
+
+        // Every cell pushes the character it was read from: digits and spaces
+        // don't depend on the operator map, and operators map back to theirs
         match action {
             Token::StringMode  => self.string_mode = false,
             Token::Char(value) => self.stack_push(value as i32),
-            token => self.stack_push(token_to_char(&token) as i32),
+            Token::Value(value) => self.stack_push(value),
+            Token::Int(value)  => self.stack_push('0' as i32 + value),
+            Token::Noop        => self.stack_push(' ' as i32),
+            token => self.stack_push(self.token_to_char(&token) as i32),
         }
     }
 
+    // The accessors from here on only read the program's state, so anything
+    // embedding the interpreter can drive it with step() and look in between
+    // steps. Positions are grid coordinates, the same ones cell() takes.
     pub fn is_running(&self) -> bool {
         self.is_running
     }
 
+    // True between a pair of `"`, where cells are pushed rather than run
+    pub fn in_string_mode(&self) -> bool {
+        self.string_mode
+    }
+
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        self.halt_reason
+    }
+
+    // Whether an `@` has ever run. A program stopped by its step limit without
+    // one has most likely forgotten its `@`.
+    pub fn quit_dispatched(&self) -> bool {
+        self.quit_dispatched
+    }
+
+    // The value popped by a Funge-98 `q`, if the program ended with one
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
     pub fn get_last_output(&self) -> String {
         self.last_output.clone()
     }
 
+    // The last output as it should be written out, which differs from
+    // get_last_output only for bytes printed in byte output mode
+    pub fn get_last_output_bytes(&self) -> Vec<u8> {
+        self.last_output_bytes.clone()
+    }
+
     pub fn xptr(&self) -> i32 {
         self.xptr
     }
@@ -399,19 +1629,743 @@ impl Program {
         self.yptr
     }
 
+    // None once `x` has set a delta that isn't one of the four directions
+    pub fn direction(&self) -> Option<Direction> {
+        Direction::from_delta(self.delta)
+    }
+
+    pub fn delta(&self) -> (i32, i32) {
+        self.delta
+    }
+
+    // The character shown at grid coordinates (x, y), or None off the grid
+    pub fn cell(&self, x: i32, y: i32) -> Option<char> {
+        self.get_token(x, y).map(|token| self.token_to_char(&token))
+    }
+
+    // The (width, height) of the torus the pointer wraps around
+    pub fn dimensions(&self) -> (i32, i32) {
+        (self.width(), self.height())
+    }
+
+    pub fn current_token(&self) -> Token {
+        self.get_token(self.xptr, self.yptr).unwrap_or(Token::Noop)
+    }
+
     pub fn get_stack(&self) -> &Vec<i32> {
         &self.stack
     }
+
+    // The grid as it currently stands, one line per row
+    pub fn render_grid(&self) -> String {
+        self.grid.iter()
+            .map(|line| line.iter()
+                 .map(|token| self.token_to_char(token))
+                 .collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    // render_grid without the spaces at the end of each row or the blank rows
+    // at the bottom, which `p` and --pad-grid tend to leave behind
+    pub fn render_grid_trimmed(&self) -> String {
+        let grid = self.render_grid();
+
+        grid.split('\n')
+            .map(|row| row.trim_end_matches(' '))
+            .collect::<Vec<&str>>()
+            .join("\n")
+            .trim_end_matches('\n')
+            .to_string()
+    }
+
+    pub fn to_svg(&self) -> String {
+        svg::render(&self.render_grid(), (self.xptr, self.yptr))
+    }
+}
+
+// Leaves out the grid, which Display already covers, and the boxed reader and RNG
+impl fmt::Debug for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Program")
+            .field("xptr", &self.xptr)
+            .field("yptr", &self.yptr)
+            .field("delta", &self.delta)
+            .field("stack", &self.stack)
+            .field("is_running", &self.is_running)
+            .field("string_mode", &self.string_mode)
+            .field("version", &self.version)
+            .field("instruction_count", &self.instruction_count)
+            .finish_non_exhaustive()
+    }
 }
 
 impl fmt::Display for Program {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let program_str = self.grid.iter()
-            .map(|line| line.iter()
-                 .map(token_to_char)
-                 .collect::<String>())
-            .fold(String::new(), |res, line| format!("{}\n{}", res, line));
+        write!(f, "{}", self.render_grid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoInput;
+
+    impl InputReader for NoInput {
+        fn read_char(&mut self) -> i32 {
+            0
+        }
+
+        fn read_int(&mut self) -> i32 {
+            0
+        }
+    }
+
+    #[test]
+    fn short_rows_are_padded_out_to_the_widest_row() {
+        let grid = vec![
+            vec![Token::Right],
+            vec![Token::Noop, Token::Noop, Token::Noop, Token::Noop, Token::Quit],
+        ];
+        let mut program = Program::new(grid, Box::new(NoInput));
+
+        for _ in 0..4 {
+            program.step();
+        }
+
+        assert_eq!((program.xptr(), program.yptr()), (4, 0));
+    }
+
+    #[test]
+    fn pointer_wraps_off_a_short_row_to_its_first_column() {
+        let grid = vec![
+            vec![Token::Right],
+            vec![Token::Noop, Token::Noop, Token::Noop, Token::Noop, Token::Quit],
+        ];
+        let mut program = Program::new(grid, Box::new(NoInput));
+        program.set_version(FungeVersion::Befunge98);
+
+        for _ in 0..5 {
+            program.step();
+        }
+
+        assert_eq!((program.xptr(), program.yptr()), (0, 0));
+        assert!(program.is_running());
+    }
+
+    #[test]
+    fn exhausted_input_pushes_eof() {
+        let mut program = Program::from_source("~~&@", Box::new(StringInputReader::new("a")));
+        program.run_to_completion();
+
+        assert_eq!(program.get_stack(), &vec![97, EOF, EOF]);
+    }
+
+    #[test]
+    fn shebang_lines_are_skipped() {
+        let source = "v\n>25*\"!iH\">:#,_@";
+        let run = |source: &str| {
+            let mut program = Program::from_source(source, Box::new(NoInput));
+            program.run_with_limit(1000)
+        };
+        let plain = run(source);
+
+        for shebang in ["#!/usr/bin/env rusty_fungus\n", "#! rusty_fungus\n"] {
+            assert_eq!(run(&format!("{}{}", shebang, source)), plain);
+        }
+        assert_eq!(plain, ("Hi!\n".to_string(), HaltReason::Quit));
+    }
+
+    #[test]
+    fn a_leading_trampoline_over_bang_is_code() {
+        let mut program = Program::from_source("#!1.@", Box::new(NoInput));
+
+        assert_eq!(program.run_with_limit(1000), ("1 ".to_string(), HaltReason::Quit));
+    }
+
+    #[test]
+    fn run_with_limit_stops_a_program_without_an_end() {
+        let mut program = Program::from_source(">", Box::new(NoInput));
+
+        assert_eq!(program.run_with_limit(1000), (String::new(), HaltReason::StepLimit));
+        assert_eq!(program.instruction_count(), 1000);
+    }
+
+    #[test]
+    fn a_seeded_rng_picks_the_same_directions() {
+        let grid = vec![vec![Token::Random]];
+        let rng = StdRng::seed_from_u64(42);
+        let mut program = Program::new_with_rng(grid, Box::new(NoInput), Box::new(rng));
+        program.set_version(FungeVersion::Befunge98);
+
+        let mut directions = vec![];
+        for _ in 0..6 {
+            program.step();
+            directions.push(program.direction().unwrap());
+        }
+
+        use Direction::*;
+        assert_eq!(directions, vec![Right, Left, Up, Up, Right, Down]);
+    }
+
+    fn run_98(source: &str, stack: Vec<i32>) -> (Program, String) {
+        let mut program = Program::from_source(source, Box::new(StringInputReader::new("")));
+        program.set_version(FungeVersion::Befunge98);
+        program.set_initial_stack(stack);
+        let output = program.run_to_completion();
+        (program, output)
+    }
+
+    #[test]
+    fn put_and_get_at_negative_coordinates() {
+        let (program, output) = run_98("\"A\"01-01-p01-01-g,@", vec![]);
+
+        assert_eq!(output, "A");
+        assert_eq!(program.halt_reason(), Some(HaltReason::Quit));
+    }
+
+    #[test]
+    fn growing_the_grid_keeps_the_pointer_on_its_cell() {
+        let mut program = Program::from_source("\"A\"01-01-p01-01-g,@", Box::new(NoInput));
+        program.set_version(FungeVersion::Befunge98);
+        for _ in 0..10 {
+            program.step();
+        }
+
+        // The put added a row above and a column to the left, so the pointer
+        // moved with the `0` after the `p` to grid position (11, 1)
+        assert_eq!(program.dimensions(), (20, 2));
+        assert_eq!(program.cell(0, 0), Some('A'));
+        assert_eq!((program.xptr(), program.yptr()), (11, 1));
+        assert_eq!(program.current_token(), Token::Int(0));
+    }
+
+    #[test]
+    fn puts_too_far_away_are_dropped() {
+        for stack in [vec![65, 0, i32::MIN], vec![65, 0, i32::MAX], vec![65, 0, -5000]] {
+            let (program, _) = run_98("\\p@", stack);
+
+            assert_eq!(program.halt_reason(), Some(HaltReason::Quit));
+            assert_eq!(program.dimensions(), (3, 1));
+        }
+    }
+
+    #[test]
+    fn get_too_far_away_pushes_zero() {
+        // The first put shifts the origin, so x + origin_x would overflow
+        let (program, _) = run_98("101-01-p\\g@", vec![0, i32::MAX]);
+
+        assert_eq!(program.get_stack(), &vec![0]);
+    }
+
+    #[test]
+    fn iterate_repeats_the_next_instruction() {
+        let (program, _) = run_98("15k:@", vec![]);
+
+        assert_eq!(program.get_stack(), &vec![1; 6]);
+    }
+
+    #[test]
+    fn iterated_output_is_kept_in_full() {
+        let (_, output) = run_98("\"cba\"3k,@", vec![]);
+        assert_eq!(output, "abc");
+
+        let mut program = Program::from_source("\"cba\"3k,@", Box::new(StringInputReader::new("")));
+        program.set_version(FungeVersion::Befunge98);
+        for _ in 0..7 {
+            program.step();
+        }
+        assert_eq!(program.get_last_output(), "abc");
+        assert_eq!(program.get_last_output_bytes(), b"abc".to_vec());
+    }
+
+    #[test]
+    fn iterating_iterate_is_a_no_op() {
+        let (program, _) = run_98("kk@", vec![1; 60000]);
+
+        assert_eq!(program.halt_reason(), Some(HaltReason::Quit));
+        assert_eq!(program.get_stack().len(), 59999);
+    }
+
+    #[test]
+    fn huge_iterate_counts_stop_at_the_step_limit() {
+        let mut program = Program::from_source("k:@", Box::new(StringInputReader::new("")));
+        program.set_version(FungeVersion::Befunge98);
+        program.set_initial_stack(vec![1, i32::MAX]);
+        program.set_max_steps(100);
+        program.run_to_completion();
+
+        assert_eq!(program.halt_reason(), Some(HaltReason::StepLimit));
+        assert_eq!(program.get_stack().len(), 101);
+    }
+
+    #[test]
+    fn diagonal_pointers_wrap_around_the_grid() {
+        // Moves one right and one down each step, wrapping off the 3x3 grid
+        let mut program = Program::from_source("11x\n   \n   ", Box::new(StringInputReader::new("")));
+        program.set_version(FungeVersion::Befunge98);
+        program.set_field_size(3, 3);
+
+        let mut positions = vec![];
+        for _ in 0..5 {
+            program.step();
+            positions.push((program.xptr(), program.yptr()));
+        }
+
+        assert_eq!(positions, vec![(1, 0), (2, 0), (0, 1), (1, 2), (2, 0)]);
+    }
+
+    #[test]
+    fn huge_deltas_wrap_without_overflowing() {
+        let (program, _) = run_98("x@", vec![i32::MAX, 0]);
+
+        assert_eq!(program.halt_reason(), Some(HaltReason::Quit));
+    }
+
+    #[test]
+    fn profiling_counts_each_operator() {
+        let mut program = Program::from_source("111111+++++...@", Box::new(StringInputReader::new("")));
+        program.set_profiling(true);
+        program.run_to_completion();
+
+        let counts = program.operator_counts();
+        assert_eq!(counts.get(&Token::Add), Some(&5));
+        assert_eq!(counts.get(&Token::PrintInt), Some(&3));
+        assert_eq!(counts.get(&Token::Int(1)), Some(&6));
+        assert_eq!(counts.get(&Token::Subtract), None);
+    }
+
+    #[test]
+    fn string_input_reader_reads_chars_then_eof() {
+        let mut reader = StringInputReader::new("hi");
+
+        assert_eq!(reader.read_char(), 'h' as i32);
+        assert_eq!(reader.read_char(), 'i' as i32);
+        assert_eq!(reader.read_char(), EOF);
+        assert_eq!(reader.read_char(), EOF);
+    }
+
+    #[test]
+    fn string_input_reader_reads_ints_then_eof() {
+        let mut reader = StringInputReader::new("7 -8x9");
+
+        assert_eq!(reader.read_int(), 7);
+        assert_eq!(reader.read_int(), -8);
+        assert_eq!(reader.read_char(), 'x' as i32);
+        assert_eq!(reader.read_int(), 9);
+        assert_eq!(reader.read_int(), EOF);
+    }
+
+    #[test]
+    fn string_input_reader_from_bytes_keeps_every_byte() {
+        let mut reader = StringInputReader::from_bytes(vec![0, 200, 255]);
+
+        assert_eq!(reader.read_char(), 0);
+        assert_eq!(reader.read_char(), 200);
+        assert_eq!(reader.read_char(), 255);
+        assert_eq!(reader.read_char(), EOF);
+    }
+
+    #[test]
+    fn parse_next_int_leaves_the_cursor_after_the_digits() {
+        let line: Vec<char> = "12 34\n".chars().collect();
+
+        assert_eq!(parse_next_int(&line, 0), Some((12, 2)));
+        assert_eq!(parse_next_int(&line, 2), Some((34, 5)));
+        assert_eq!(parse_next_int(&line, 5), None);
+    }
+
+    #[test]
+    fn to_svg_boxes_the_current_cell() {
+        let mut program = Program::from_source(">v\n @", Box::new(StringInputReader::new("")));
+        program.step();
+
+        let svg = program.to_svg();
+        assert!(svg.contains("<rect x=\"12\" y=\"0\""));
+        assert!(svg.contains(">@</text>"));
+    }
+
+    fn run_98_with_depth(source: &str, stack: Vec<i32>, depth: usize) -> Program {
+        let mut program = Program::from_source(source, Box::new(StringInputReader::new("")));
+        program.set_version(FungeVersion::Befunge98);
+        program.set_initial_stack(stack);
+        program.set_max_stack_depth(depth);
+        program.run_to_completion();
+        program
+    }
+
+    #[test]
+    fn blocks_carry_values_and_restore_the_storage_offset() {
+        // { moves 2 and 3 onto a new stack and } hands 3 back, with the
+        // offset of the cell after { restored underneath in between
+        let (program, _) = run_98("123 2{ 1}@", vec![]);
+
+        assert_eq!(program.get_stack(), &vec![1, 3]);
+        assert_eq!(program.storage_offset, (0, 0));
+    }
+
+    #[test]
+    fn negative_begin_block_pads_the_old_stack_with_zeros() {
+        let (program, _) = run_98("7 02-{ u@", vec![]);
+
+        // u with nothing on the new stack pops 0 moves, leaving the zeros and
+        // saved offset underneath
+        assert_eq!(program.stack_stack, vec![vec![7, 0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn blocks_respect_the_stack_depth_limit() {
+        for (source, stack) in [
+            ("{@", vec![-5_000_000]),
+            ("{@", vec![5_000_000]),
+            ("1{}@", vec![5_000_000]),
+            ("1{u@", vec![5_000_000]),
+            ("1{u@", vec![-5_000_000]),
+        ] {
+            let program = run_98_with_depth(source, stack, 10);
+
+            assert_eq!(program.halt_reason(), Some(HaltReason::StackOverflow), "{}", source);
+            assert!(program.get_stack().len() <= 10);
+        }
+    }
+
+    #[test]
+    fn extension_operators_bind_only_unmapped_characters() {
+        let mut program = Program::from_source("  P..@", Box::new(StringInputReader::new("")));
+
+        assert!(program.set_extension_operator('P', ExtensionOperator::PushPosition).is_ok());
+        assert!(program.set_extension_operator('+', ExtensionOperator::PushPosition).is_err());
+        assert!(program.set_extension_operator('7', ExtensionOperator::PushPosition).is_err());
+
+        // P pushes its y and then its x
+        assert_eq!(program.run_to_completion(), "2 0 ");
+    }
+
+    #[test]
+    fn values_without_a_char_survive_put_and_get() {
+        let mut program = Program::from_source("01-00p00g.00g,@", Box::new(StringInputReader::new("")));
+
+        assert_eq!(program.run_to_completion(), "-1 \u{FFFD}");
+    }
+
+    #[test]
+    fn width_stays_put_when_the_widest_cell_is_blanked() {
+        let mut program = Program::from_source("84*90p@  X\n@", Box::new(StringInputReader::new("")));
+        program.set_version(FungeVersion::Befunge98);
+        assert_eq!(program.dimensions(), (10, 2));
+
+        program.run_to_completion();
+
+        assert_eq!(program.cell(9, 0), Some(' '));
+        assert_eq!(program.dimensions(), (10, 2));
+    }
+
+    #[test]
+    fn chars_past_unicode_print_as_the_replacement_character() {
+        let mut program = Program::from_source(",@", Box::new(NoInput));
+        program.set_initial_stack(vec![0x110000]);
+
+        assert_eq!(program.run_to_completion(), "\u{FFFD}");
+    }
+
+    #[test]
+    fn fetch_char_pushes_the_next_cell_and_skips_it() {
+        let (program, output) = run_98("'A.@", vec![]);
+
+        assert_eq!(output, "65 ");
+        assert_eq!(program.halt_reason(), Some(HaltReason::Quit));
+    }
+
+    #[test]
+    fn store_char_writes_the_next_cell_and_skips_it() {
+        // The `.` becomes a `1` that is never run
+        let (program, output) = run_98("'1s.@", vec![]);
+
+        assert_eq!(output, "");
+        assert_eq!(program.cell(3, 0), Some('1'));
+        assert!(program.get_stack().is_empty());
+    }
+
+    #[test]
+    fn strict_stack_warns_once_per_underflowing_instruction() {
+        // `+` pops twice from an empty stack but is reported only once
+        let mut program = Program::from_source("+@", Box::new(NoInput));
+        program.set_strict_stack(true);
+        program.run_to_completion();
+
+        assert_eq!(program.take_warnings(), vec![RuntimeWarning::StackUnderflow { x: 0, y: 0, operator: '+' }]);
+        assert!(program.take_warnings().is_empty());
+    }
+
+    fn traced_run(source: &str) -> Vec<TraceEntry> {
+        let mut program = Program::from_source(source, Box::new(NoInput));
+        program.set_tracing(true);
+        program.run_to_completion();
+        program.take_trace()
+    }
+
+    #[test]
+    fn tracing_records_every_step() {
+        let entry = |step: u64, op: char, stack_len: usize| TraceEntry {
+            step,
+            x: step as i32,
+            y: 0,
+            direction: (1, 0),
+            op,
+            stack_len,
+        };
+
+        assert_eq!(traced_run("12+.@"), vec![
+            entry(0, '1', 0),
+            entry(1, '2', 1),
+            entry(2, '+', 2),
+            entry(3, '.', 1),
+            entry(4, '@', 0),
+        ]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn traces_round_trip_through_json() {
+        let trace = traced_run("12+.@");
+        let json = serde_json::to_string(&trace).unwrap();
+
+        assert_eq!(serde_json::from_str::<Vec<TraceEntry>>(&json).unwrap(), trace);
+    }
+
+    #[test]
+    fn rerunning_a_trace_finds_the_first_difference() {
+        let trace = traced_run("12+.@");
+        let rerun = || Program::from_source("12+.@", Box::new(NoInput));
+        assert_eq!(rerun().verify_against_trace(&trace), Ok(()));
+
+        let mut corrupted = trace;
+        corrupted[2].x = 7;
+        assert_eq!(rerun().verify_against_trace(&corrupted), Err(TraceMismatch {
+            step: 2,
+            expected: (7, 0),
+            actual: (2, 0),
+            expected_direction: (1, 0),
+            actual_direction: (1, 0),
+        }));
+    }
+
+    #[test]
+    fn reverse_sends_the_pointer_back_the_way_it_came() {
+        // `r` bounces back over the `1`, then wraps round to the `@`
+        let (program, output) = run_98("1r.@", vec![]);
+
+        assert_eq!(output, "");
+        assert_eq!(program.get_stack(), &vec![1, 1]);
+        assert_eq!(program.direction(), Some(Direction::Left));
+    }
+
+    #[test]
+    fn turns_rotate_from_every_direction() {
+        use Direction::*;
+        let turn = |token: Token, from: Direction| {
+            let mut program = Program::new(vec![vec![token]], Box::new(NoInput));
+            program.set_version(FungeVersion::Befunge98);
+            program.delta = from.delta();
+            program.step();
+            program.direction().unwrap()
+        };
+
+        for (from, left, right) in [(Right, Up, Down), (Up, Left, Right), (Left, Down, Up), (Down, Right, Left)] {
+            assert_eq!(turn(Token::TurnLeft, from), left, "turning left from {:?}", from);
+            assert_eq!(turn(Token::TurnRight, from), right, "turning right from {:?}", from);
+        }
+    }
+
+    #[test]
+    fn clear_stack_empties_it_and_later_pops_give_zero() {
+        let (program, output) = run_98("123n.@", vec![]);
+
+        assert_eq!(output, "0 ");
+        assert!(program.get_stack().is_empty());
+    }
+
+    #[test]
+    fn heatmap_counts_visits_to_each_cell() {
+        // Counts down from 5, running the loop body five times and jumping
+        // back up the `^` after all but the last
+        let mut program = Program::from_source("5v\n >1-:v\n ^   _@", Box::new(NoInput));
+        program.set_heatmap(true);
+        program.run_to_completion();
+
+        assert_eq!(program.visit_count(2, 1), 5);
+        assert_eq!(program.visit_count(1, 2), 4);
+        assert_eq!(program.visit_count(6, 2), 1);
+        assert_eq!(program.max_visit_count(), 5);
+    }
+
+    #[test]
+    fn reset_undoes_self_modification() {
+        let mut program = Program::from_source("67*00p@", Box::new(NoInput));
+        program.run_to_completion();
+        assert_eq!(program.cell(0, 0), Some('*'));
+
+        program.reset();
+
+        assert_eq!(program.render_grid(), "67*00p@");
+        assert!(program.is_running());
+        assert_eq!(program.instruction_count(), 0);
+    }
+
+    #[test]
+    fn befunge93_puts_outside_the_field_are_dropped() {
+        let mut program = Program::from_source("158*5*:p@", Box::new(NoInput));
+        program.run_to_completion();
+
+        assert_eq!(program.halt_reason(), Some(HaltReason::Quit));
+        assert_eq!(program.render_grid(), "158*5*:p@");
+        assert_eq!(program.dimensions(), (80, 25));
+    }
+
+    #[test]
+    fn a_runaway_duplicate_loop_stops_at_the_stack_depth_limit() {
+        let mut program = Program::from_source("1>:", Box::new(NoInput));
+        program.set_max_stack_depth(100);
+        program.run_with_limit(100_000);
+
+        assert_eq!(program.halt_reason(), Some(HaltReason::StackOverflow));
+        assert_eq!(program.get_stack().len(), 100);
+    }
+
+    #[test]
+    fn debug_output_shows_the_pointer_and_stack() {
+        let mut program = Program::from_source("12@", Box::new(NoInput));
+        program.step();
+        program.step();
+
+        assert_eq!(format!("{:?}", program),
+                   "Program { xptr: 2, yptr: 0, delta: (1, 0), stack: [1, 2], is_running: true, \
+                    string_mode: false, version: Befunge93, instruction_count: 2, .. }");
+    }
+
+    #[test]
+    fn round_robin_random_cycles_through_the_directions() {
+        // Each `?` sends the pointer round the 2x2 square to the next one
+        let mut program = Program::from_source("??\n??", Box::new(NoInput));
+        program.set_version(FungeVersion::Befunge98);
+        program.set_random_strategy(RandomStrategy::RoundRobin);
+
+        let mut moves = vec![];
+        for _ in 0..4 {
+            program.step();
+            moves.push((program.direction().unwrap(), (program.xptr(), program.yptr())));
+        }
+
+        use Direction::*;
+        assert_eq!(moves, vec![(Right, (1, 0)), (Down, (1, 1)), (Left, (0, 1)), (Up, (0, 0))]);
+    }
+
+    #[test]
+    fn an_empty_program_runs_until_stopped() {
+        for version in [FungeVersion::Befunge93, FungeVersion::Befunge98] {
+            let mut program = Program::from_source("", Box::new(NoInput));
+            program.set_version(version);
+
+            assert_eq!(program.run_with_limit(100), (String::new(), HaltReason::StepLimit));
+        }
+    }
+
+    #[test]
+    fn stepping_from_off_the_grid_is_an_error() {
+        let mut program = Program::from_source("@", Box::new(NoInput));
+        program.xptr = 500;
+        let error = RuntimeError::PointerOutOfBounds { x: 500, y: 0 };
+
+        assert_eq!(program.step(), StepResult::Error(error));
+        assert_eq!(program.halt_reason(), Some(HaltReason::Error(error)));
+        assert!(!program.is_running());
+    }
+
+    #[test]
+    fn befunge93_gets_inside_the_field_read_spaces() {
+        // (79, 24) is the far corner of the 80x25 field, well past the program
+        let mut program = Program::from_source("98*7+38*g.@", Box::new(NoInput));
+
+        assert_eq!(program.run_to_completion(), "32 ");
+    }
+
+    #[test]
+    fn the_pointer_wraps_at_the_configured_field_size() {
+        let mut program = Program::from_source(">", Box::new(NoInput));
+        program.set_field_size(5, 3);
+        assert_eq!(program.dimensions(), (5, 3));
+
+        for _ in 0..5 {
+            program.step();
+        }
+
+        assert_eq!((program.xptr(), program.yptr()), (0, 0));
+    }
+
+    #[test]
+    fn gets_outside_the_configured_field_push_zero() {
+        let mut program = Program::from_source("30g.55g.@", Box::new(NoInput));
+        program.set_field_size(10, 3);
+
+        assert_eq!(program.run_to_completion(), "46 0 ");
+    }
+
+    #[test]
+    fn without_wrapping_the_pointer_leaves_at_the_edge() {
+        let mut program = Program::from_source(">", Box::new(NoInput));
+        program.set_wrapping(false);
+
+        assert_eq!(program.run_with_limit(1000), (String::new(), HaltReason::LeftGrid));
+        assert_eq!(program.instruction_count(), 80);
+    }
+
+    #[test]
+    fn adding_past_i32_max_wraps() {
+        let mut program = Program::from_source("+.@", Box::new(NoInput));
+        program.set_initial_stack(vec![2147483647, 1]);
+
+        assert_eq!(program.run_to_completion(), "-2147483648 ");
+    }
+
+    #[test]
+    fn preloaded_values_are_there_for_the_first_instruction() {
+        let mut program = Program::from_source("+.@", Box::new(NoInput));
+        program.set_initial_stack(vec![3, 4]);
+
+        assert_eq!(program.run_to_completion(), "7 ");
+    }
+
+    #[test]
+    fn padded_grids_stay_rectangular_and_run_the_same() {
+        let source = "'X94*0pv\n\n       >25*\"!iH\">:#,_@";
+        let run = |pad_grid: bool| {
+            let mut program = Program::from_source(source, Box::new(NoInput));
+            program.set_version(FungeVersion::Befunge98);
+            program.set_pad_grid(pad_grid);
+            let output = program.run_with_limit(1000);
+            (program, output)
+        };
+
+        let (padded, padded_output) = run(true);
+        let (_, jagged_output) = run(false);
+
+        // The put at (36, 0) widened the grid after it was padded
+        let widths: Vec<usize> = padded.render_grid().split('\n').map(|row| row.chars().count()).collect();
+        assert_eq!(widths, vec![37; 3]);
+        assert_eq!(padded_output, jagged_output);
+        assert_eq!(padded_output, ("Hi!\n".to_string(), HaltReason::Quit));
+    }
+
+    #[test]
+    fn trimmed_rendering_drops_trailing_spaces_and_blank_rows() {
+        let mut program = Program::from_source("12  \n@ ", Box::new(NoInput));
+        program.set_version(FungeVersion::Befunge98);
+        program.set_pad_grid(true);
+        // Widens the grid and adds two blank rows below it
+        program.set_token(6, 3, Token::Noop);
 
-        write!(f, "{}", program_str)
+        assert_eq!(program.render_grid(), "12     \n@      \n       \n       ");
+        assert_eq!(program.render_grid_trimmed(), "12\n@");
     }
 }
@@ -0,0 +1,51 @@
+use rustyfungus::{Program, StringInputReader};
+
+use std::io;
+use std::io::{BufRead, IsTerminal, Write};
+
+// Keeps a line like `>1<` from spinning forever; --max-steps overrides it
+const REPL_STEP_LIMIT: u64 = 100_000;
+
+// Each line runs on a fresh program with no input, starting at its left edge
+// and halting once the pointer walks off either end instead of wrapping
+fn eval_line<F: Fn(&mut Program)>(line: &str, configure: &F) -> io::Result<()> {
+    let mut program = Program::from_source(line, Box::new(StringInputReader::new("")));
+    program.set_max_steps(REPL_STEP_LIMIT);
+    configure(&mut program);
+    program.set_field_size(line.chars().count() as i32, 1);
+    program.set_wrapping(false);
+
+    let mut output = vec![];
+    crate::run_program_to(&mut program, &mut output, &mut io::stderr(), false, None)?;
+
+    let mut stdout = io::stdout();
+    if !output.is_empty() {
+        stdout.write_all(&output)?;
+        writeln!(stdout)?;
+    }
+    writeln!(stdout, "stack: {:?}", program.get_stack())?;
+    stdout.flush()
+}
+
+pub fn run_repl<F: Fn(&mut Program)>(configure: F) -> io::Result<()> {
+    let stdin = io::stdin();
+    let prompt = stdin.is_terminal();
+    let mut stdout = io::stdout();
+
+    loop {
+        if prompt {
+            write!(stdout, "> ")?;
+            stdout.flush()?;
+        }
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let line = line.trim_end_matches(['\r', '\n']);
+        if !line.is_empty() {
+            eval_line(line, &configure)?;
+        }
+    }
+}
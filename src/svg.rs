@@ -0,0 +1,73 @@
+const CELL_WIDTH: i32 = 12;
+const CELL_HEIGHT: i32 = 20;
+const FONT_SIZE: i32 = 16;
+const FONT_FAMILY: &str = "monospace";
+const HIGHLIGHT_COLOR: &str = "#f0a030";
+
+fn escape(c: char) -> String {
+    match c {
+        '&'  => "&amp;".to_string(),
+        '<'  => "&lt;".to_string(),
+        '>'  => "&gt;".to_string(),
+        '"'  => "&quot;".to_string(),
+        '\'' => "&apos;".to_string(),
+        c    => c.to_string(),
+    }
+}
+
+// Lays each cell out as its own glyph so the columns line up regardless of how
+// the viewer's monospace font handles spacing, with a box around the pointer
+pub fn render(grid: &str, pointer: (i32, i32)) -> String {
+    let rows: Vec<Vec<char>> = grid.split('\n').map(|row| row.chars().collect()).collect();
+
+    let columns = rows.iter()
+        .map(|row| row.len() as i32)
+        .max()
+        .unwrap_or(0)
+        .max(pointer.0 + 1);
+    let lines = (rows.len() as i32).max(pointer.1 + 1);
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"{}\" font-size=\"{}\">\n",
+        columns * CELL_WIDTH, lines * CELL_HEIGHT, FONT_FAMILY, FONT_SIZE);
+
+    svg += &format!(
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>\n",
+        pointer.0 * CELL_WIDTH, pointer.1 * CELL_HEIGHT, CELL_WIDTH, CELL_HEIGHT, HIGHLIGHT_COLOR);
+
+    for (y, row) in rows.iter().enumerate() {
+        for (x, c) in row.iter().enumerate() {
+            svg += &format!(
+                "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n",
+                x as i32 * CELL_WIDTH + CELL_WIDTH / 2,
+                (y as i32 + 1) * CELL_HEIGHT - (CELL_HEIGHT - FONT_SIZE),
+                escape(*c));
+        }
+    }
+
+    svg + "</svg>\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_lays_out_cells_and_boxes_the_pointer() {
+        let svg = render("a<\nb", (1, 0));
+
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"24\" height=\"40\""));
+        assert!(svg.contains("<rect x=\"12\" y=\"0\" width=\"12\" height=\"20\""));
+        assert!(svg.contains("<text x=\"6\" y=\"16\" text-anchor=\"middle\">a</text>"));
+        assert!(svg.contains("<text x=\"18\" y=\"16\" text-anchor=\"middle\">&lt;</text>"));
+        assert!(svg.contains("<text x=\"6\" y=\"36\" text-anchor=\"middle\">b</text>"));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn render_grows_to_fit_a_pointer_past_the_text() {
+        let svg = render("a", (3, 2));
+
+        assert!(svg.contains("width=\"48\" height=\"60\""));
+    }
+}
@@ -1,7 +1,8 @@
 use bimap::BiMap;
 use std::iter::FromIterator;
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Token {
     Add,
     Subtract,
@@ -29,13 +30,30 @@ pub enum Token {
     ReadInt,
     ReadChar,
     Quit,
-    Int(u8),
+    FetchChar,
+    StoreChar,
+    Iterate,
+    SetVector,
+    Reverse,
+    TurnLeft,
+    TurnRight,
+    ClearStack,
+    BeginBlock,
+    EndBlock,
+    StackUnderStack,
+    QuitWithCode,
+    Split,
+    // A digit 0-9 from the source
+    Int(i32),
     Noop,
     Char(char),
+    // A value stored by `p` or `s` that isn't a character, like a negative
+    // number, kept as is so `g` reads back exactly what was put
+    Value(i32),
 }
 
 lazy_static! {
-    static ref CHAR_TOKEN_MAP: bimap::hash::BiHashMap<char, Token> = BiMap::from_iter(vec![
+    static ref CHAR_TOKEN_MAP: OperatorMap = BiMap::from_iter(vec![
         ('+', Token::Add),
         ('-', Token::Subtract),
         ('*', Token::Multiply),
@@ -62,24 +80,185 @@ lazy_static! {
         ('&', Token::ReadInt),
         ('~', Token::ReadChar),
         ('@', Token::Quit),
+        ('\'',Token::FetchChar),
+        ('s', Token::StoreChar),
+        ('k', Token::Iterate),
+        ('x', Token::SetVector),
+        ('r', Token::Reverse),
+        ('[', Token::TurnLeft),
+        (']', Token::TurnRight),
+        ('n', Token::ClearStack),
+        ('{', Token::BeginBlock),
+        ('}', Token::EndBlock),
+        ('u', Token::StackUnderStack),
+        ('q', Token::QuitWithCode),
+        ('t', Token::Split),
         (' ', Token::Noop),
     ]);
 }
 
+// Maps characters to the operators they run. Everything below has a variant
+// taking a custom map, for programs written with a remapped alphabet.
+pub type OperatorMap = BiMap<char, Token>;
+
+pub fn default_operator_map() -> OperatorMap {
+    CHAR_TOKEN_MAP.clone()
+}
+
 pub fn token_to_char(token: &Token) -> char {
+    token_to_char_with(&CHAR_TOKEN_MAP, token)
+}
+
+// An operator missing from the map (say, one dropped from a custom map) shows
+// up as an empty cell rather than panicking mid-render
+pub fn token_to_char_with(map: &OperatorMap, token: &Token) -> char {
     match token {
-        Token::Int(value)   => (value + ('0' as u8)) as char,
+        Token::Int(value)   => char::from_digit(*value as u32, 10).unwrap_or(char::REPLACEMENT_CHARACTER),
         Token::Char(value)  => *value,
-        value               => *CHAR_TOKEN_MAP.get_by_right(&value).unwrap(),
+        Token::Value(_)     => char::REPLACEMENT_CHARACTER,
+        value               => *map.get_by_right(value).unwrap_or(&' '),
     }
 }
 
 pub fn char_to_token(character: char) -> Token {
+    char_to_token_with(&CHAR_TOKEN_MAP, character)
+}
+
+pub fn char_to_token_with(map: &OperatorMap, character: char) -> Token {
     match character {
-        '0'..='9' => Token::Int(character.to_digit(10).unwrap() as u8),
-        value     => match CHAR_TOKEN_MAP.get_by_left(&value) {
+        '0'..='9' => Token::Int(character.to_digit(10).unwrap() as i32),
+        value     => match map.get_by_left(&value) {
             Some(c) => *c,
             None    => Token::Char(value),
         }
     }
 }
+
+// A short explanation of each instruction, as shown in the debugger's legend.
+// Binary operators name their operands by stack position, so `73-` is
+// "second - top" with 7 second and 3 on top.
+pub fn describe_token(token: &Token) -> &'static str {
+    match token {
+        Token::Add             => "push second + top",
+        Token::Subtract        => "push second - top",
+        Token::Multiply        => "push second * top",
+        Token::Divide          => "push second / top",
+        Token::Modulo          => "push second % top",
+        Token::Not             => "1 if 0, else 0",
+        Token::Greater         => "second > top ? 1 : 0",
+        Token::Right           => "go right",
+        Token::Left            => "go left",
+        Token::Up              => "go up",
+        Token::Down            => "go down",
+        Token::Random          => "go a random way",
+        Token::HorizontalIf    => "right if 0, else left",
+        Token::VerticalIf      => "down if 0, else up",
+        Token::StringMode      => "toggle string mode",
+        Token::Duplicate       => "duplicate top",
+        Token::Swap            => "swap top two",
+        Token::Discard         => "discard top",
+        Token::PrintInt        => "print as a number",
+        Token::PrintChar       => "print as a character",
+        Token::Bridge          => "skip the next cell",
+        Token::Get             => "push cell at x y",
+        Token::Put             => "store v at x y",
+        Token::ReadInt         => "read a number",
+        Token::ReadChar        => "read a character",
+        Token::Quit            => "end the program",
+        Token::FetchChar       => "push the next cell",
+        Token::StoreChar       => "store in next cell",
+        Token::Iterate         => "run next cell n times",
+        Token::SetVector       => "move by dx dy",
+        Token::Reverse         => "turn around",
+        Token::TurnLeft        => "turn left",
+        Token::TurnRight       => "turn right",
+        Token::ClearStack      => "clear the stack",
+        Token::BeginBlock      => "push a new stack",
+        Token::EndBlock        => "pop the top stack",
+        Token::StackUnderStack => "move between stacks",
+        Token::QuitWithCode    => "end with exit code",
+        Token::Split           => "split off a reversed IP",
+        Token::Int(_)          => "push the digit",
+        Token::Noop            => "do nothing",
+        Token::Char(_)         => "do nothing",
+        Token::Value(_)        => "stored value, no-op",
+    }
+}
+
+// Windows line endings are dropped, and a tab is a single no-op cell just like
+// a space rather than being expanded to the next tab stop
+pub fn lines_to_token_matrix(lines: std::str::Lines) -> Vec<Vec<Token>> {
+    lines_to_token_matrix_with(&CHAR_TOKEN_MAP, lines)
+}
+
+pub fn lines_to_token_matrix_with(map: &OperatorMap, lines: std::str::Lines) -> Vec<Vec<Token>> {
+    lines.map(|line| {
+        line.strip_suffix('\r').unwrap_or(line).chars().map(|c| match c {
+            '\t' => Token::Noop,
+            c    => char_to_token_with(map, c),
+        }).collect()
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [Token; 40] = [
+        Token::Add, Token::Subtract, Token::Multiply, Token::Divide, Token::Modulo,
+        Token::Not, Token::Greater, Token::Right, Token::Left, Token::Up, Token::Down,
+        Token::Random, Token::HorizontalIf, Token::VerticalIf, Token::StringMode,
+        Token::Duplicate, Token::Swap, Token::Discard, Token::PrintInt, Token::PrintChar,
+        Token::Bridge, Token::Get, Token::Put, Token::ReadInt, Token::ReadChar, Token::Quit,
+        Token::FetchChar, Token::StoreChar, Token::Iterate, Token::SetVector, Token::Reverse,
+        Token::TurnLeft, Token::TurnRight, Token::ClearStack, Token::BeginBlock,
+        Token::EndBlock, Token::StackUnderStack, Token::QuitWithCode, Token::Split, Token::Noop,
+    ];
+
+    #[test]
+    fn every_operator_has_a_character() {
+        assert_eq!(CHAR_TOKEN_MAP.len(), OPERATORS.len());
+
+        for token in OPERATORS.iter() {
+            let c = *CHAR_TOKEN_MAP.get_by_right(token).unwrap();
+            assert_eq!(token_to_char(token), c);
+            assert_eq!(char_to_token(c), *token);
+        }
+    }
+
+    #[test]
+    fn tokens_without_a_character_fall_back() {
+        let mut map = default_operator_map();
+        map.remove_by_right(&Token::Add);
+
+        assert_eq!(token_to_char_with(&map, &Token::Add), ' ');
+        assert_eq!(token_to_char(&Token::Int(7)), '7');
+        assert_eq!(token_to_char(&Token::Int(12)), char::REPLACEMENT_CHARACTER);
+        assert_eq!(token_to_char(&Token::Value(-1)), char::REPLACEMENT_CHARACTER);
+        assert_eq!(token_to_char(&Token::Char('z')), 'z');
+    }
+
+    #[test]
+    fn every_mapped_operator_is_described() {
+        for (c, token) in default_operator_map().iter() {
+            assert!(!describe_token(token).is_empty(), "{} has no description", c);
+        }
+    }
+
+    #[test]
+    fn line_endings_and_tabs_become_plain_cells() {
+        // The lone `\r` has no `\n` after it, so lines() leaves it for us
+        let grid = lines_to_token_matrix("1\t.\r\n@\r".lines());
+
+        assert_eq!(grid, vec![
+            vec![Token::Int(1), Token::Noop, Token::PrintInt],
+            vec![Token::Quit],
+        ]);
+    }
+
+    #[test]
+    fn tokens_debug_print_as_their_variant() {
+        assert_eq!(format!("{:?}", Token::Add), "Add");
+        assert_eq!(format!("{:?}", Token::Int(3)), "Int(3)");
+    }
+}
@@ -0,0 +1,49 @@
+use crate::token::Token;
+
+// Befunge-93 runs on a fixed 80x25 torus, while Befunge-98 wraps around the
+// bounding box of whatever has been written to the grid (Lahey-space, for
+// cardinal directions; diagonal pointers simply wrap around the torus). The
+// Funge-98 instructions implemented so far are ', s, k, x, r, [, ], n, {, },
+// u, q and t; j, w, z, ;, a-f, y and the i/o/= system calls are still missing
+// and behave like any unknown character.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FungeVersion {
+    Befunge93,
+    Befunge98,
+}
+
+pub const BEFUNGE93_WIDTH: i32 = 80;
+pub const BEFUNGE93_HEIGHT: i32 = 25;
+
+impl FungeVersion {
+    // Whether the token is an instruction in this version rather than a no-op
+    pub fn supports(self, token: Token) -> bool {
+        match token {
+            Token::FetchChar
+                | Token::StoreChar
+                | Token::Iterate
+                | Token::SetVector
+                | Token::Reverse
+                | Token::TurnLeft
+                | Token::TurnRight
+                | Token::ClearStack
+                | Token::BeginBlock
+                | Token::EndBlock
+                | Token::StackUnderStack
+                | Token::QuitWithCode
+                | Token::Split => self == FungeVersion::Befunge98,
+            _ => true,
+        }
+    }
+
+    // Whether `g` and `p` may reach the cell. Befunge-93 is confined to its
+    // playfield (normally 80x25), while Befunge-98 space grows on demand (up to
+    // a cap the program enforces when writing).
+    pub fn in_playfield(self, x: i32, y: i32, field: (i32, i32)) -> bool {
+        match self {
+            FungeVersion::Befunge93 => (0..field.0).contains(&x) && (0..field.1).contains(&y),
+            FungeVersion::Befunge98 => true,
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use rustyfungus::{default_operator_map, Direction, FungeVersion, HaltReason, Program, RuntimeError, StepResult, StringInputReader, Token};
+
+fn program(source: &str) -> Program {
+    Program::from_source(source, Box::new(StringInputReader::new("")))
+}
+
+#[test]
+fn stepping_moves_the_pointer_and_turns_it() {
+    let mut program = program("v\n>25*\"!iH\">:#,_@");
+
+    assert_eq!((program.xptr(), program.yptr()), (0, 0));
+    assert_eq!(program.direction(), Some(Direction::Right));
+
+    assert_eq!(program.step(), StepResult::Running);
+    assert_eq!((program.xptr(), program.yptr()), (0, 1));
+    assert_eq!(program.direction(), Some(Direction::Down));
+
+    assert_eq!(program.step(), StepResult::Running);
+    assert_eq!((program.xptr(), program.yptr()), (1, 1));
+    assert_eq!(program.direction(), Some(Direction::Right));
+}
+
+#[test]
+fn cells_read_back_the_source() {
+    let program = program("v\n>25*\"!iH\">:#,_@");
+
+    assert_eq!(program.cell(0, 0), Some('v'));
+    assert_eq!(program.cell(3, 1), Some('*'));
+    // Past the end of a short row is an implicit space, and past the field is
+    // nothing at all
+    assert_eq!(program.cell(5, 0), Some(' '));
+    assert_eq!(program.cell(80, 0), None);
+    assert_eq!(program.cell(-1, 0), None);
+}
+
+#[test]
+fn dimensions_follow_the_version() {
+    let mut program = program("v\n>25*\"!iH\">:#,_@");
+    assert_eq!(program.dimensions(), (80, 25));
+
+    program.set_version(FungeVersion::Befunge98);
+    assert_eq!(program.dimensions(), (15, 2));
+}
+
+#[test]
+fn stepping_to_the_end() {
+    let mut program = program("v\n>25*\"!iH\">:#,_@");
+
+    let mut output = String::new();
+    while program.step() == StepResult::Running {
+        output += &program.get_last_output();
+    }
+
+    assert_eq!(output, "Hi!\n");
+    assert_eq!(program.halt_reason(), Some(HaltReason::Quit));
+    assert!(!program.is_running());
+}
+
+#[test]
+fn observing_a_split_reports_each_pointer_s_own_turns() {
+    let mut program = program("t>>>@");
+    program.set_version(FungeVersion::Befunge98);
+
+    // The parent runs `t`, then the copy heading left gets its turn at the
+    // `@` one cell behind it; neither pointer turned
+    let split = program.step_observed();
+    assert_eq!(split.token, Token::Split);
+    assert!(!split.changed_direction);
+    assert_eq!(program.ip_count(), 2);
+
+    let copy = program.step_observed();
+    assert_eq!((copy.x, copy.y, copy.token), (4, 0, Token::Quit));
+    assert!(!copy.changed_direction);
+    assert_eq!(program.ip_count(), 1);
+
+    let parent = program.step_observed();
+    assert_eq!((parent.x, parent.y), (1, 0));
+    assert!(!parent.changed_direction);
+}
+
+#[test]
+fn stepping_over_left_turns_the_pointer_left() {
+    let mut program = program(" <@");
+    program.step();
+    program.step();
+
+    assert_eq!(program.direction(), Some(Direction::Left));
+    assert_eq!((program.xptr(), program.yptr()), (0, 0));
+}
+
+#[test]
+fn instruction_count_covers_the_wrap_back_to_the_end() {
+    // `<`, the 78 spaces from column 79 back to column 2, then `@`
+    let mut program = program("<@");
+    program.run_to_completion();
+
+    assert_eq!(program.instruction_count(), 80);
+}
+
+#[test]
+fn restoring_a_snapshot_rewinds_a_step() {
+    let mut program = program("12+.@");
+    program.step();
+    let state = program.snapshot();
+
+    program.step();
+    assert_eq!(program.get_stack(), &vec![1, 2]);
+
+    program.restore(state);
+    assert_eq!(program.get_stack(), &vec![1]);
+    assert_eq!((program.xptr(), program.yptr()), (1, 0));
+    assert_eq!(program.instruction_count(), 1);
+    assert_eq!(program.run_to_completion(), "3 ");
+}
+
+#[test]
+fn an_unmodified_grid_renders_as_its_source() {
+    let source = "12+v\n@.\"<";
+
+    assert_eq!(program(source).render_grid(), source);
+}
+
+#[test]
+fn display_prints_the_rows_without_a_trailing_newline() {
+    assert_eq!(program("12+.\n@v<>").to_string(), "12+.\n@v<>");
+}
+
+#[test]
+fn next_output_yields_the_whole_output_piece_by_piece() {
+    let source = "v\n>25*\"!iH\">:#,_@";
+    let mut stepped = program(source);
+
+    let pieces: Vec<String> = std::iter::from_fn(|| stepped.next_output()).collect();
+
+    assert_eq!(pieces.concat(), program(source).run_to_completion());
+    assert_eq!(pieces.concat(), "Hi!\n");
+}
+
+#[test]
+fn set_start_runs_from_the_given_cell() {
+    let mut program = program("@.21");
+    assert_eq!(program.set_start(3, 0, Direction::Left), Ok(()));
+
+    assert_eq!(program.run_to_completion(), "2 ");
+    assert_eq!(program.get_stack(), &vec![1]);
+}
+
+#[test]
+fn set_start_rejects_cells_off_the_grid() {
+    let mut program = program("@.21");
+
+    for (x, y) in [(80, 0), (-1, 0), (0, 25)] {
+        assert_eq!(program.set_start(x, y, Direction::Right), Err(RuntimeError::PointerOutOfBounds { x, y }));
+    }
+    assert_eq!((program.xptr(), program.yptr()), (0, 0));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn a_loaded_checkpoint_carries_on_where_it_left_off() {
+    let source = "v\n>25*\"!iH\">:#,_@";
+    let path = std::env::temp_dir().join(format!("rusty_fungus_checkpoint_{}.json", std::process::id()));
+    let mut program = program(source);
+    let mut output = String::new();
+    while output.is_empty() {
+        program.step();
+        output += &program.get_last_output();
+    }
+
+    program.save_checkpoint(&path).unwrap();
+    let mut resumed = Program::load_checkpoint(&path, Box::new(StringInputReader::new(""))).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(output, "H");
+    assert_eq!(resumed.get_stack(), program.get_stack());
+    let rest = resumed.run_to_completion();
+    assert_eq!(rest, program.run_to_completion());
+    assert_eq!(output + &rest, "Hi!\n");
+}
+
+#[test]
+fn a_remapped_operator_runs_under_its_new_character() {
+    let run = |source: &str| {
+        let mut map = default_operator_map();
+        map.insert('A', Token::Add);
+        Program::from_source_with_operator_map(source, Box::new(StringInputReader::new("")), map).run_to_completion()
+    };
+
+    assert_eq!(run("12A.@"), "3 ");
+    // `+` lost its meaning when `A` took over addition
+    assert_eq!(run("12+.@"), "2 ");
+}
+
+#[test]
+fn string_mode_spans_the_quotes() {
+    let mut program = program("\"ab\"@");
+    let mut modes = vec![program.in_string_mode()];
+    while program.step() == StepResult::Running {
+        modes.push(program.in_string_mode());
+    }
+
+    // On at the opening quote, off again once the closing one has run
+    assert_eq!(modes, vec![false, true, true, true, false]);
+}
+
+#[test]
+fn observed_steps_report_output_and_turns() {
+    let mut program = program("1.v\n  @");
+
+    let push = program.step_observed();
+    assert!(!push.produced_output && !push.changed_direction);
+
+    let print = program.step_observed();
+    assert_eq!(print.token, Token::PrintInt);
+    assert!(print.produced_output && !print.changed_direction);
+
+    let turn = program.step_observed();
+    assert_eq!((turn.x, turn.y, turn.token), (2, 0, Token::Down));
+    assert!(!turn.produced_output && turn.changed_direction);
+}
@@ -0,0 +1,241 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rusty_fungus"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start rusty_fungus");
+
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout_of(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn cat_stops_at_the_end_of_stdin() {
+    let output = run(&["test/cat.bef"], "fixed input");
+
+    assert_eq!(stdout_of(&output), "fixed input");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn read_int_pushes_eof_once_stdin_is_exhausted() {
+    let output = run(&["test/read_ints.bef"], "12");
+
+    assert_eq!(stdout_of(&output), "12 -1 ");
+}
+
+#[test]
+fn interactive_input_needs_the_debugger() {
+    let output = run(&["--interactive", "test/read_ints.bef"], "");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--debug"));
+}
+
+#[test]
+fn read_int_takes_one_number_at_a_time() {
+    let output = run(&["test/read_ints.bef"], "12 34\n");
+    assert_eq!(stdout_of(&output), "12 34 ");
+
+    let output = run(&["test/read_ints.bef"], "12\n34\n");
+    assert_eq!(stdout_of(&output), "12 34 ");
+}
+
+#[test]
+fn trace_writes_one_line_per_step_to_stderr() {
+    let output = run(&["--trace", "-"], "12+.@");
+
+    assert_eq!(stdout_of(&output), "3 ");
+    assert_eq!(String::from_utf8_lossy(&output.stderr),
+               "(0,0) Right 1 | stack=[]\n\
+                (1,0) Right 2 | stack=[1]\n\
+                (2,0) Right + | stack=[1, 2]\n\
+                (3,0) Right . | stack=[3]\n\
+                (4,0) Right @ | stack=[]\n");
+}
+
+#[test]
+fn manifest_reports_each_program_and_fails_on_a_mismatch() {
+    let output = run(&["--manifest", "test/mixed.manifest"], "");
+    let stdout = stdout_of(&output);
+
+    assert!(stdout.starts_with("PASS test/hello.bef\nFAIL test/voila.bef: expected "));
+    assert!(stdout.ends_with("1 passed, 1 failed\n"));
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn manifest_passes_when_every_program_does() {
+    // The step limit makes a broken program fail rather than hang the test run
+    for manifest in ["test/samples.manifest", "test/conformance/conformance.manifest"] {
+        let output = run(&["--max-steps", "1000000", "--manifest", manifest], "");
+        let stdout = stdout_of(&output);
+
+        assert!(stdout.ends_with(" passed, 0 failed\n"), "{}", stdout);
+        assert_eq!(output.status.code(), Some(0));
+    }
+}
+
+#[test]
+fn exit_codes_report_why_the_run_ended() {
+    let cases: [(&[&str], &str, i32, &str); 4] = [
+        (&[], "@", 0, "halted"),
+        (&["--max-steps", "10"], ">", 2, "step-limit"),
+        (&["--max-stack-depth", "2"], "123@", 3, "stack-limit"),
+        (&["--detect-loops"], ">", 6, "likely-infinite-loop"),
+    ];
+
+    for (flags, source, code, name) in cases {
+        let mut args = flags.to_vec();
+        args.extend(["--json-status", "-"]);
+        let output = run(&args, source);
+
+        assert_eq!(output.status.code(), Some(code), "{}", source);
+        assert!(String::from_utf8_lossy(&output.stderr).contains(&format!("\"status\":\"{}\"", name)));
+    }
+}
+
+#[test]
+fn quit_with_code_passes_its_code_through() {
+    let output = run(&["--mode", "98", "--json-status", "-"], "5q");
+
+    assert_eq!(output.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("\"status\":\"halted\",\"exit_code\":5"));
+}
+
+#[test]
+fn quit_codes_a_process_cannot_exit_with_become_one() {
+    for source in ["88*4*q", "1-q"] {
+        let output = run(&["--mode", "98", "-"], source);
+
+        assert_eq!(output.status.code(), Some(1), "{}", source);
+    }
+}
+
+#[test]
+fn repl_runs_each_line_on_its_own() {
+    let output = run(&["--repl"], "99+.\n12\n");
+
+    assert_eq!(stdout_of(&output), "18 \nstack: []\nstack: [1, 2]\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn debug_text_writes_a_frame_per_step() {
+    let output = run(&["--debug-text", "-"], "12+.@");
+    let stdout = stdout_of(&output);
+
+    assert_eq!(stdout.matches("----\n").count(), 5);
+    assert!(stdout.starts_with("Step 1\n1[2]+.@\nStack: [1]\nOutput: \"\"\n----\n"));
+    assert!(stdout.contains("Step 4\n12+.[@]\nStack: []\nOutput: \"3 \"\n"));
+}
+
+#[test]
+fn manifest_entries_can_pick_their_mode() {
+    let output = run(&["--manifest", "test/funge98/funge98.manifest"], "");
+
+    assert_eq!(stdout_of(&output), "PASS test/funge98/split.bef\n1 passed, 0 failed\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn record_frames_writes_a_numbered_file_per_step() {
+    let dir = std::env::temp_dir().join(format!("rusty_fungus_frames_{}", std::process::id()));
+    let output = run(&["--record-frames", dir.to_str().unwrap(), "-"], "12+.@");
+
+    let mut frames: Vec<String> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    frames.sort();
+    let fourth_frame = fs::read_to_string(dir.join("frame_0003.txt")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(stdout_of(&output), "3 ");
+    assert_eq!(frames, ["frame_0000.txt", "frame_0001.txt", "frame_0002.txt", "frame_0003.txt", "frame_0004.txt"]);
+    assert_eq!(fourth_frame, "Step 4\n12+.[@]\nStack: []\nOutput: \"3 \"\n----\n");
+}
+
+#[test]
+fn crlf_files_run_like_their_unix_twins() {
+    let path = std::env::temp_dir().join(format!("rusty_fungus_crlf_{}.bef", std::process::id()));
+    fs::write(&path, "v\r\n>12+.@\r\n").unwrap();
+    let output = run(&[path.to_str().unwrap()], "");
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(stdout_of(&output), "3 ");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn no_int_space_drops_the_space_after_numbers_in_each_mode() {
+    for mode in ["93", "98"] {
+        let output = run(&["--no-int-space", "--mode", mode, "-"], "67*.@");
+
+        assert_eq!(stdout_of(&output), "42", "mode {}", mode);
+    }
+}
+
+#[test]
+fn byte_output_writes_the_raw_byte() {
+    let output = run(&["--byte-output", "-"], "58*5*,@");
+
+    assert_eq!(output.stdout, vec![0xC8]);
+}
+
+#[test]
+fn dump_stack_prints_the_final_stack_to_stderr() {
+    let output = run(&["--dump-stack", "-"], "123@");
+
+    assert_eq!(stdout_of(&output), "");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Final stack (bottom to top): [1, 2, 3]\n");
+}
+
+#[test]
+fn input_file_feeds_the_program_instead_of_stdin() {
+    let path = std::env::temp_dir().join(format!("rusty_fungus_input_{}.txt", std::process::id()));
+    fs::write(&path, "from the file\n").unwrap();
+    let output = run(&["--input-file", path.to_str().unwrap(), "test/cat.bef"], "");
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(stdout_of(&output), "from the file\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn step_limit_hint_only_shows_when_no_at_ran() {
+    let hint = "hint: program halted by step limit; no @ encountered";
+
+    let looping = run(&["--max-steps", "10", "-"], ">");
+    assert!(String::from_utf8_lossy(&looping.stderr).contains(hint));
+
+    // The copy made by `t` ends at the `@`, while the parent bounces between
+    // `v` and `^` until the limit
+    let split = run(&["--mode", "98", "--max-steps", "10", "-"], "tv@\n ^");
+    assert_eq!(split.status.code(), Some(2));
+    assert!(!String::from_utf8_lossy(&split.stderr).contains(hint));
+}
+
+#[test]
+fn stack_preloads_values_before_the_first_step() {
+    let output = run(&["--stack", "3,4", "-"], "+.@");
+
+    assert_eq!(stdout_of(&output), "7 ");
+}
+
+#[test]
+fn a_huge_iterate_count_is_stopped_by_the_step_limit() {
+    let output = run(&["--mode", "98", "--stack", "1,2000000000", "--max-steps", "50", "-"], "k:@");
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Stopped after reaching the limit of 50 steps\n"));
+}